@@ -0,0 +1,194 @@
+/* generated from src/ffi.rs by cbindgen, do not edit */
+
+#ifndef QART_H
+#define QART_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum QartError {
+  /**
+   * nothing went wrong
+   */
+  QART_ERROR_OK = 0,
+  /**
+   * a pointer that was passed in was null
+   */
+  QART_ERROR_NULL_POINTER = 1,
+  /**
+   * the version is not between 1 and 40
+   */
+  QART_ERROR_INVALID_VERSION = 2,
+  /**
+   * the payload was not set, is not utf-8, cant be encoded as ISO 8859-1, or is too long for the version
+   */
+  QART_ERROR_INVALID_PAYLOAD = 3,
+  /**
+   * the target was not set, or its buffer does not match its width and height
+   */
+  QART_ERROR_INVALID_TARGET = 4,
+  /**
+   * a setting is out of range, like a module size of 0 or a width too small for the code
+   */
+  QART_ERROR_INVALID_OPTION = 5,
+  /**
+   * the code could not be built
+   */
+  QART_ERROR_BUILD_FAILED = 6,
+  /**
+   * the library panicked, this is a bug
+   */
+  QART_ERROR_PANIC = 7,
+} QartError;
+
+typedef struct QartCode QartCode;
+
+typedef struct QartOptions QartOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * the message of the last error returned on this thread, or null if there wasnt one. the string is owned by the
+ * library and stays valid until the next call that fails on this thread
+ */
+const char *qart_last_error_message(void);
+
+/**
+ * new options with the same defaults as the command line, and no version, payload or target. free them with
+ * qart_options_free
+ */
+struct QartOptions *qart_options_new(void);
+
+/**
+ * frees options made by qart_options_new, null is ignored
+ *
+ * # Safety
+ * options must be null or returned by qart_options_new, and not freed already
+ */
+void qart_options_free(struct QartOptions *options);
+
+/**
+ * the version (size) of the code, 1-40
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_version(struct QartOptions *options, uint8_t version);
+
+/**
+ * the url the code points to, as a nul terminated utf-8 string. it is copied
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and payload must be a nul terminated string
+ */
+enum QartError qart_options_set_payload(struct QartOptions *options, const char *payload);
+
+/**
+ * the image the code will look like, as width * height * 4 bytes of rgba, row by row. it is copied
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and rgba must point to at least len bytes
+ */
+enum QartError qart_options_set_target(struct QartOptions *options, const uint8_t *rgba, size_t len, uint32_t width, uint32_t height);
+
+/**
+ * pixels darker than this are black
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_threshold(struct QartOptions *options, uint8_t threshold);
+
+/**
+ * chooses the threshold from the brightness histogram of the target
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_threshold_auto(struct QartOptions *options);
+
+/**
+ * makes the code the same every time it is built with the same options
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_seed(struct QartOptions *options, uint64_t seed);
+
+/**
+ * the side length of each module in the rgba image, in pixels
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_module_size(struct QartOptions *options, uint32_t module_size);
+
+/**
+ * the side length of the whole rgba image in pixels, instead of the module size. the modules are made as big as fits,
+ * and the pixels left over are split evenly around the code. 0 goes back to using the module size
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_width(struct QartOptions *options, uint32_t width);
+
+/**
+ * the width of the light margin around the code in modules, 10 by default. scanners expect at least 4
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and not freed already
+ */
+enum QartError qart_options_set_quiet_zone(struct QartOptions *options, uint32_t quiet_zone);
+
+/**
+ * builds the code and stores it in code, which has to be freed with qart_code_free. code is left alone on failure
+ *
+ * # Safety
+ * options must be returned by qart_options_new, and code must point to a writable pointer
+ */
+enum QartError qart_build(const struct QartOptions *options, struct QartCode **code);
+
+/**
+ * frees a code stored by qart_build, null is ignored
+ *
+ * # Safety
+ * code must be null or stored by qart_build, and not freed already
+ */
+void qart_code_free(struct QartCode *code);
+
+/**
+ * the modules of the code, side_len * side_len bytes row by row, where 1 is dark and 0 is light. side_len is set if it
+ * isnt null. the buffer belongs to the code
+ *
+ * # Safety
+ * code must be stored by qart_build, and not freed already
+ */
+const uint8_t *qart_code_modules(const struct QartCode *code, uint32_t *side_len);
+
+/**
+ * the image of the code with its border, width * height * 4 bytes of rgba row by row. width and height are set if
+ * they arent null. the buffer belongs to the code
+ *
+ * # Safety
+ * code must be stored by qart_build, and not freed already
+ */
+const uint8_t *qart_code_rgba(const struct QartCode *code, uint32_t *width, uint32_t *height);
+
+/**
+ * the brightness threshold that was used, useful when it was chosen automatically
+ *
+ * # Safety
+ * code must be stored by qart_build, and not freed already
+ */
+uint8_t qart_code_threshold(const struct QartCode *code);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* QART_H */
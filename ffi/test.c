@@ -0,0 +1,117 @@
+/*
+ * a small harness for the c abi. build the library with `cargo build --release --features ffi`, then from the root of
+ * the repository:
+ *
+ *     cc ffi/test.c -Iffi -Ltarget/release -lqart -o target/ffi-test && LD_LIBRARY_PATH=target/release target/ffi-test
+ *
+ * it exits with 0 if every check passes, and writes the code it built to code.ppm
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "qart.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                            \
+    do {                                                                       \
+        if (!(cond)) {                                                         \
+            fprintf(stderr, "%s:%d: %s failed\n", __FILE__, __LINE__, #cond); \
+            failures++;                                                        \
+        }                                                                      \
+    } while (0)
+
+/* a dark circle on a light background */
+static uint8_t *circle(uint32_t width, uint32_t height) {
+    uint8_t *rgba = malloc((size_t)width * height * 4);
+    for (uint32_t y = 0; y < height; y++) {
+        for (uint32_t x = 0; x < width; x++) {
+            long dx = (long)x - width / 2, dy = (long)y - height / 2;
+            uint8_t value = dx * dx + dy * dy < (long)(width * width / 9) ? 20 : 235;
+            uint8_t *pixel = rgba + ((size_t)y * width + x) * 4;
+            pixel[0] = pixel[1] = pixel[2] = value;
+            pixel[3] = 255;
+        }
+    }
+    return rgba;
+}
+
+static QartOptions *options(const uint8_t *rgba, uint32_t width, uint32_t height) {
+    QartOptions *options = qart_options_new();
+    CHECK(qart_options_set_version(options, 5) == QART_ERROR_OK);
+    CHECK(qart_options_set_payload(options, "https://example.com") == QART_ERROR_OK);
+    CHECK(qart_options_set_target(options, rgba, (size_t)width * height * 4, width, height) == QART_ERROR_OK);
+    CHECK(qart_options_set_threshold(options, 128) == QART_ERROR_OK);
+    CHECK(qart_options_set_seed(options, 42) == QART_ERROR_OK);
+    CHECK(qart_options_set_module_size(options, 4) == QART_ERROR_OK);
+    return options;
+}
+
+int main(void) {
+    const uint32_t width = 90, height = 90;
+    uint8_t *rgba = circle(width, height);
+
+    /* a code can be built, and the same seed gives the same code */
+    QartOptions *opts = options(rgba, width, height);
+    QartCode *code = NULL, *again = NULL;
+    CHECK(qart_build(opts, &code) == QART_ERROR_OK);
+    CHECK(qart_build(opts, &again) == QART_ERROR_OK);
+    if (code && again) {
+        uint32_t side_len = 0, image_width = 0, image_height = 0;
+        const uint8_t *modules = qart_code_modules(code, &side_len);
+        const uint8_t *pixels = qart_code_rgba(code, &image_width, &image_height);
+        CHECK(side_len == 37);
+        CHECK(image_width == (37 + 2 * 10) * 4 && image_height == image_width);
+        CHECK(qart_code_threshold(code) == 128);
+
+        /* the top left finder pattern is dark on its outside ring and light just inside it */
+        CHECK(modules[0] == 1 && modules[6] == 1 && modules[side_len + 1] == 0);
+
+        uint32_t again_side_len = 0;
+        const uint8_t *again_modules = qart_code_modules(again, &again_side_len);
+        CHECK(again_side_len == side_len && memcmp(modules, again_modules, side_len * side_len) == 0);
+
+        FILE *file = fopen("code.ppm", "wb");
+        if (file) {
+            fprintf(file, "P6\n%u %u\n255\n", image_width, image_height);
+            for (size_t i = 0; i < (size_t)image_width * image_height; i++) {
+                fwrite(pixels + i * 4, 1, 3, file);
+            }
+            fclose(file);
+        }
+    }
+    qart_code_free(code);
+    qart_code_free(again);
+
+    /* failures are reported with an error code and a message, and leave the code alone */
+    code = NULL;
+    CHECK(qart_options_set_version(opts, 41) == QART_ERROR_INVALID_VERSION);
+    CHECK(qart_last_error_message() != NULL);
+    CHECK(qart_options_set_module_size(opts, 0) == QART_ERROR_INVALID_OPTION);
+    CHECK(qart_options_set_target(opts, rgba, 10, width, height) == QART_ERROR_INVALID_TARGET);
+    CHECK(qart_options_set_payload(opts, "https://example.com/a/path/that/is/too/long/for/version/one") ==
+          QART_ERROR_OK);
+    CHECK(qart_options_set_version(opts, 1) == QART_ERROR_OK);
+    CHECK(qart_build(opts, &code) == QART_ERROR_INVALID_PAYLOAD);
+    CHECK(code == NULL);
+    CHECK(qart_options_set_payload(opts, NULL) == QART_ERROR_NULL_POINTER);
+    CHECK(qart_build(NULL, &code) == QART_ERROR_NULL_POINTER);
+    qart_options_free(opts);
+
+    QartOptions *empty = qart_options_new();
+    CHECK(qart_options_set_version(empty, 5) == QART_ERROR_OK);
+    CHECK(qart_build(empty, &code) == QART_ERROR_INVALID_PAYLOAD);
+    CHECK(qart_options_set_payload(empty, "https://example.com") == QART_ERROR_OK);
+    CHECK(qart_build(empty, &code) == QART_ERROR_INVALID_TARGET);
+    CHECK(code == NULL);
+    qart_options_free(empty);
+
+    free(rgba);
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,87 @@
+// the options shared by the javascript and python bindings, and how they turn into the options of the library. each
+// binding adds its own constructor on top
+
+use crate::optimize::Optimize;
+use crate::qr;
+use crate::style::{ModuleStyle, PatternStyle};
+use crate::target::Threshold;
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+#[cfg(feature = "wasm-bindgen")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+// the settings that can be changed from javascript and python, the rest are left at their defaults
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+#[cfg_attr(feature = "python", pyclass(get_all, set_all))]
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    // the side length of each of the modules in pixels, only used by build
+    pub module_size: u32,
+    // the side length of the whole image in pixels, instead of module_size. only used by build
+    pub width: Option<u32>,
+    // the width of the light margin around the code in modules, only used by build
+    pub quiet_zone: u32,
+    // pixels darker than this are black, 128 like the command line by default. it is chosen from the image when it
+    // is undefined or None
+    pub threshold: Option<u8>,
+    // makes the output the same every time, a random seed is used if this is undefined or None
+    pub seed: Option<u64>,
+    // distribute uncontrollable modules randomly instead of based off of priority
+    pub random: bool,
+    // the number of candidate codes to build and keep the best of, only used by build. 0 or 1 builds just one
+    pub candidates: u32,
+    // draw alignment and timing patterns as dots, with the rest of the module following the image. only used by build
+    pub dot_patterns: bool,
+    // draw data modules as a square core this wide, as a fraction of the module, with the rest showing the image in
+    // gray. only used by build
+    pub module_core: Option<f32>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let defaults = qr::Options::default();
+        Options {
+            module_size: defaults.module_size,
+            width: defaults.width,
+            quiet_zone: defaults.quiet_zone,
+            threshold: match defaults.threshold {
+                Threshold::Fixed(threshold) => Some(threshold),
+                Threshold::Otsu => None,
+            },
+            seed: None,
+            random: false,
+            candidates: 0,
+            dot_patterns: false,
+            module_core: None,
+        }
+    }
+}
+
+impl From<&Options> for qr::Options {
+    fn from(options: &Options) -> Self {
+        qr::Options {
+            module_size: options.module_size,
+            width: options.width,
+            quiet_zone: options.quiet_zone,
+            threshold: options.threshold.map_or(Threshold::Otsu, Threshold::Fixed),
+            random: options.random,
+            seed: options.seed,
+            optimize: (options.candidates > 1).then(|| Optimize {
+                iterations: options.candidates as usize,
+                ..Default::default()
+            }),
+            patterns: if options.dot_patterns {
+                PatternStyle::Dots {
+                    min_core: 1,
+                    finders: false,
+                }
+            } else {
+                PatternStyle::Solid
+            },
+            modules: options
+                .module_core
+                .map_or(ModuleStyle::Solid, |core| ModuleStyle::Cores { core }),
+            ..Default::default()
+        }
+    }
+}
@@ -3,19 +3,45 @@ use crate::{
     arrs::{BitArr, BitArrMethods, ByteArr, ByteArrMethods},
     gf::{self, Field},
 };
+use std::ops::Range;
 use std::rc::Rc;
 
 pub struct Block {
     num_data_bytes: usize,
     block_bytes: ByteArr,
+    // data bits that are part of the encoded text, and cant be changed
+    fixed: Vec<bool>,
     basis: Vec<Option<ByteArr>>,
-    used: Vec<Option<ByteArr>>,
-    numeric_data_start: usize,
-    numeric_data_end: usize,
+    // rows that have been used to set a bit, along with the index of that bit
+    used: Vec<(usize, ByteArr)>,
+    guards: Vec<Guard>,
+    // the number of bits that were refused to keep a number valid, and had to be cleared in the end
+    refused: usize,
+}
+
+// the leading bits of a group of numeric bits. a 10 bit group is at most 999 as long as one of its top 5 bits is 0,
+// a 7 bit group is at most 99 as long as one of its top 2 bits is 0, and a 4 bit group is at most 9 if its top bit is 0.
+// so that one of them can always be cleared at the end, a row is held back from the basis for one of the guard bits,
+// until the target sets one of the others to 0
+struct Guard {
+    bits: Range<usize>,
+    // the whole group and the largest number it can hold, if all of it is in this block
+    group: Option<(Range<usize>, u16)>,
+    // the guard bit that is held back, and the row that changes it without changing any set bit
+    reserve: Option<(usize, ByteArr)>,
+    // whether the held back bit was asked to be set to 1
+    refused: bool,
 }
 
 impl Block {
-    pub fn new(num_data_bytes: usize, field: Rc<Field>, block_bits: BitArr) -> Self {
+    // numeric_groups are the indexes of each group of numeric bits that starts in this block, cut off where the block
+    // ends, along with the full length of the group
+    pub fn new(
+        num_data_bytes: usize,
+        field: Rc<Field>,
+        block_bits: BitArr,
+        numeric_groups: Vec<(Range<usize>, usize)>,
+    ) -> Self {
         let in_block_bytes = block_bits.to_byte_arr();
 
         let num_ec_bytes = in_block_bytes.len() - num_data_bytes;
@@ -28,7 +54,7 @@ impl Block {
 
         let mut basis =
             vec![Some(Vec::with_capacity(num_data_bytes + num_ec_bytes)); num_data_bytes * 8];
-        let used = vec![None; num_data_bytes * 8];
+        let used = Vec::with_capacity(num_data_bytes * 8);
 
         for (index, mask) in basis.iter_mut().enumerate() {
             let mask_ref = mask.as_mut().unwrap();
@@ -41,43 +67,106 @@ impl Block {
             mask_ref.extend_from_slice(&ec_bytes);
         }
 
-        let mut numeric_data_start = 0;
-        let mut numeric_data_end = 0;
-        let mut data_found = false;
+        let fixed = (0..basis.len())
+            .map(|index| !block_bits[index].can_edit())
+            .collect::<Vec<_>>();
         for index in 0..basis.len() {
-            if !block_bits[index].can_edit() {
+            if fixed[index] {
                 basis[index].take();
             }
-            if block_bits[index].is_num() {
-                if data_found {
-                    assert!(block_bits[index - 1].is_num());
-                    numeric_data_end += 1;
-                } else {
-                    numeric_data_start = index;
-                    numeric_data_end = index + 1;
-                    data_found = true
-                }
-            }
         }
 
+        // the rows of the basis only have one data bit set at this point, so the row for the first guard bit can be
+        // held back without touching the others
+        let guards = numeric_groups
+            .into_iter()
+            .map(|(group, len)| {
+                let (guard_len, max) = match len {
+                    10 => (5, 999),
+                    7 => (2, 99),
+                    _ => (1, 9),
+                };
+                let bits = group.start..(group.start + guard_len).min(group.end);
+                let reserve = bits
+                    .clone()
+                    .find_map(|index| basis[index].take().map(|row| (index, row)));
+                Guard {
+                    bits,
+                    group: (group.len() == len).then_some((group, max)),
+                    reserve,
+                    refused: false,
+                }
+            })
+            .collect();
+
         Self {
             num_data_bytes,
             block_bytes,
+            fixed,
             basis,
             used,
-            numeric_data_start,
-            numeric_data_end,
+            guards,
+            refused: 0,
         }
     }
 
     // TODO: the setting phase seems to take up the most time in the process, but i am not sure to what degree it can be optimized.
     // could use another look
     pub fn set(&mut self, index: usize, val: u8) -> bool {
-        let mut found: Option<Vec<u8>> = None;
-        let mut found_index = 0;
+        let guard_index = self
+            .guards
+            .iter()
+            .position(|guard| guard.reserve.is_some() && guard.bits.contains(&index));
+
+        let targ = match guard_index {
+            Some(g) if self.guards[g].reserve.as_ref().unwrap().0 == index => {
+                // the held back bit can be set to 0 with its own row, or to 1 if another guard bit can be held back
+                // instead
+                if val == 0 {
+                    self.guards[g].reserve.take().unwrap().1
+                } else if let Some(targ) = self.move_reserve(g) {
+                    targ
+                } else {
+                    self.guards[g].refused = true;
+                    return false;
+                }
+            }
+            _ => match self.take_row(index) {
+                Some(targ) => targ,
+                None => return false,
+            },
+        };
+
+        // zeroes out that bit in the used and held back rows too
+        self.eliminate(index, &targ);
+
+        // so now we have found a row where the bit at index is 1, and then cut that bit from all the other rows
+        // now we apply that row to the block if we need to, and since we took that row out of basis,
+        // that bit cannot be changed again
+        if self.block_bytes.bit_at(index) != val {
+            for j in 0..targ.len() {
+                self.block_bytes[j] ^= targ[j];
+            }
+        }
+
+        // move the row into used
+        self.used.push((index, targ));
+
+        // a guard bit set to 0 keeps its number valid by itself, so the held back row can go back into the basis
+        if let Some(g) = guard_index.filter(|_| val == 0) {
+            if let Some((_, row)) = self.guards[g].reserve.take() {
+                self.basis.push(Some(row));
+            }
+        }
+
+        true
+    }
 
-        // finds a row in the matrix where the bit at the index is set, and zeroes out that bit at all the other rows
-        for (j, row_opt) in self.basis.iter_mut().enumerate() {
+    // finds a row in the basis where the bit at the index is set, and zeroes out that bit at all the other rows
+    fn take_row(&mut self, index: usize) -> Option<ByteArr> {
+        let mut found: Option<ByteArr> = None;
+
+        for row_opt in self.basis.iter_mut() {
             if let Some(row) = row_opt {
                 if row.bit_at(index) == 0 {
                     continue;
@@ -88,75 +177,86 @@ impl Block {
                     }
                 } else {
                     found = row_opt.take();
-                    found_index = j;
                 }
             }
         }
 
-        if let Some(targ) = found {
+        found
+    }
 
-            // zeroes out that bit in the used rows too
-            for row_opt in self.used.iter_mut() {
-                if let Some(row) = row_opt {
-                    if row.bit_at(index) == 1 {
-                        for k in 0..row.len() {
-                            row[k] ^= targ[k];
-                        }
-                    }
+    // zeroes out the bit at index in the used and held back rows, so that using them later doesnt change it
+    fn eliminate(&mut self, index: usize, targ: &ByteArr) {
+        let held = self
+            .guards
+            .iter_mut()
+            .flat_map(|guard| guard.reserve.as_mut());
+        for (_, row) in self.used.iter_mut().chain(held) {
+            if row.bit_at(index) == 1 {
+                for k in 0..row.len() {
+                    row[k] ^= targ[k];
                 }
             }
-    
-            // so now we have found a row where the bit at index is 1, and then cut that bit from all the other rows
-            // now we apply that row to the block if we need to, and since we took that row out of basis,
-            // that bit cannot be changed again
-            if self.block_bytes.bit_at(index) != val {
-                for j in 0..targ.len() {
-                    self.block_bytes[j] ^= targ[j];
-                }
-            }
-    
-            // move the row into used
-            self.used[found_index] = Some(targ);
-
-            true
-        } else {
-            false
         }
     }
 
-    pub fn reset(&mut self, index: usize) {
-        // if the bit has been set already, and it needs to be reset because it has caused an invalid number generation,
-        // this function resets the bit by finding the row that was used to set it, and reversing the operation
+    // holds back a different bit of the guard, and returns the row of the one that was held back so it can be set to 1.
+    // returns none if none of the others can be changed anymore
+    fn move_reserve(&mut self, g: usize) -> Option<ByteArr> {
+        let held = self.guards[g].reserve.as_ref()?.0;
+        let (index, row) = self.guards[g]
+            .bits
+            .clone()
+            .filter(|index| *index != held)
+            .find_map(|index| self.take_row(index).map(|row| (index, row)))?;
 
-        // if the bit is already set to the desired value, no need to do anything
-        if self.block_bytes.bit_at(index) == 0 {
-            return;
-        }
+        self.eliminate(index, &row);
+        self.guards[g]
+            .reserve
+            .replace((index, row))
+            .map(|(_, old)| old)
+    }
 
-        let mut found = false;
-        for row_opt in self.used.iter() {
-            if let Some(row) = row_opt {
-                if row.bit_at(index) != 0 {
+    // clears a guard bit of every number that would otherwise be too big. the held back rows dont change any bit that
+    // was set, or any other guard bit that is held back, but they can change the lower bits of other numbers, so this is
+    // repeated until nothing changes. every pass clears at least one guard bit for good, so it cant go on for long
+    pub fn fix_numbers(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for guard in self.guards.iter() {
+                let Some((index, row)) = guard.reserve.as_ref() else {
+                    continue;
+                };
+                // the lower bits can still keep the number in range when all of the guard bits are 1
+                let in_range = guard.group.as_ref().is_some_and(|(group, max)| {
+                    let value = group
+                        .clone()
+                        .fold(0, |value, i| value << 1 | self.block_bytes.bit_at(i) as u16);
+                    value <= *max
+                });
+                if self.block_bytes.bit_at(*index) == 1 && !in_range {
                     for k in 0..row.len() {
                         self.block_bytes[k] ^= row[k];
                     }
-                    // row_opt.take();
-                    found = true;
-                    break;
+                    self.refused += guard.refused as usize;
+                    changed = true;
                 }
             }
         }
-        if found {
-            return;
-        } else {
-            assert!(self.set(index, 0));
-        }
     }
 
-    pub fn iter_nums<'b>(&'b self) -> impl Iterator<Item = (usize, u8)> + 'b {
-        BlockIter::new(self, self.numeric_data_start, self.numeric_data_end)
-            .enumerate()
-            .map(move |(i, bit)| (self.numeric_data_start + i, bit))
+    // whether the bit at index is part of the encoded text. ec bits can always be changed
+    pub fn is_fixed(&self, index: usize) -> bool {
+        self.fixed.get(index).copied().unwrap_or(false)
+    }
+
+    // the number of bits that can be set, before any are
+    pub fn num_free(&self) -> usize {
+        self.fixed.iter().filter(|fixed| !**fixed).count()
+    }
+
+    pub fn num_refused(&self) -> usize {
+        self.refused
     }
 
     pub fn iter_data_ec<'b>(
@@ -179,6 +279,14 @@ impl Block {
         self.block_bytes.to_bits()
     }
 
+    pub fn num_data_bytes(&self) -> usize {
+        self.num_data_bytes
+    }
+
+    pub fn num_ec_bytes(&self) -> usize {
+        self.block_bytes.len() - self.num_data_bytes
+    }
+
     pub fn get(&self, index: usize) -> u8 {
         self.block_bytes.bit_at(index)
     }
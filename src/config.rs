@@ -0,0 +1,196 @@
+// named presets of command line options, read from a toml file. a preset is a table under [presets] whose keys are
+// the long names of options, and options that are also given on the command line override it:
+//
+//     [presets.brand]
+//     threshold = "auto"
+//     module-size = 8
+//     dot-patterns = true
+//
+// the preset is turned into more command line arguments, so its values are checked the same way as typed ones
+
+use anyhow::{anyhow, Context};
+use clap::parser::ValueSource;
+use clap::{Arg, Command};
+use std::ffi::OsString;
+use std::fs;
+use toml::{Table, Value};
+
+// the preset used when --config is given without --preset
+const DEFAULT_PRESET: &str = "default";
+
+// the command line arguments, with the options from the chosen preset added on
+pub fn args(command: Command) -> anyhow::Result<Vec<OsString>> {
+    with_preset(command, std::env::args_os().collect())
+}
+
+fn with_preset(command: Command, mut args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let matches = command.clone().get_matches_from(&args);
+    let Some((name, matches)) = matches.subcommand() else {
+        return Ok(args);
+    };
+    // only some subcommands take a config
+    let Some(path) = matches.try_get_one::<String>("config").ok().flatten() else {
+        return Ok(args);
+    };
+    let preset_name = matches
+        .get_one::<String>("preset")
+        .map_or(DEFAULT_PRESET, String::as_str);
+
+    let config: Table = fs::read_to_string(path)
+        .with_context(|| format!("Could not read config {path}"))?
+        .parse()
+        .with_context(|| format!("Could not parse config {path}"))?;
+    let preset = config
+        .get("presets")
+        .and_then(|presets| presets.get(preset_name))
+        .and_then(Value::as_table)
+        .ok_or_else(|| anyhow!("{path} has no preset named \"{preset_name}\""))?;
+
+    let subcommand = command
+        .find_subcommand(name)
+        .expect("the subcommand was just parsed");
+    for (key, value) in preset {
+        let long = key.replace('_', "-");
+        let find = |subcommand: &Command| {
+            subcommand
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()))
+                .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "preset"))
+                .cloned()
+        };
+        // a preset can be shared by subcommands, so options that only some of them have are skipped by the rest
+        let Some(arg) = find(subcommand) else {
+            if command.get_subcommands().any(|other| find(other).is_some()) {
+                continue;
+            }
+            return Err(anyhow!(
+                "preset \"{preset_name}\" sets {key}, which isnt an option"
+            ));
+        };
+        // options that cant be used together with one from the command line are left out too, like --module-size
+        // when --width is given
+        let given = |arg: &Arg| {
+            matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        };
+        if given(&arg)
+            || subcommand
+                .get_arguments()
+                .any(|other| given(other) && conflicts(subcommand, &arg, other))
+        {
+            continue;
+        }
+
+        // arrays are for options that can be repeated
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Boolean(set) if !arg.get_action().takes_values() => {
+                    if *set {
+                        args.push(format!("--{long}").into());
+                    }
+                    continue;
+                }
+                _ if !arg.get_action().takes_values() => {
+                    return Err(anyhow!(
+                        "{key} in preset \"{preset_name}\" must be true or false"
+                    ))
+                }
+                Value::String(value) => value.clone(),
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(anyhow!(
+                        "{key} in preset \"{preset_name}\" must be a string or a number"
+                    ))
+                }
+            };
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(args)
+}
+
+// whether either of two options of a command cant be used together with the other
+fn conflicts(command: &Command, a: &Arg, b: &Arg) -> bool {
+    let with = |a: &Arg, b: &Arg| {
+        command
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|arg| arg.get_id() == b.get_id())
+    };
+    with(a, b) || with(b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cli;
+    use clap::CommandFactory;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // tests run at the same time, so each config gets its own file
+    static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+    // the arguments that the preset added to a command line
+    fn added(preset: &str, args: &[&str]) -> anyhow::Result<Vec<String>> {
+        let path = std::env::temp_dir().join(format!(
+            "qart-preset-{}-{}.toml",
+            std::process::id(),
+            CONFIGS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, format!("[presets.default]\n{preset}")).unwrap();
+
+        let mut given = vec!["qart".to_string()];
+        given.extend(args.iter().map(|arg| arg.to_string()));
+        given.extend(["--config".to_string(), path.display().to_string()]);
+        let merged = with_preset(Cli::command(), given.iter().map(OsString::from).collect());
+        fs::remove_file(&path).unwrap();
+
+        let merged = merged?;
+        assert!(Cli::command().try_get_matches_from(&merged).is_ok());
+        Ok(merged[given.len()..]
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    const BUILD: [&str; 5] = ["build", "5", "https://e.co", "in.png", "out.png"];
+
+    #[test]
+    fn presets_add_options() {
+        let preset = "module-size = 8\nthreshold = \"auto\"\ndot-patterns = true\nrandom = false";
+        let added = added(preset, &BUILD).unwrap();
+        assert_eq!(
+            added,
+            ["--dot-patterns", "--module-size=8", "--threshold=auto"]
+        );
+    }
+
+    #[test]
+    fn the_command_line_overrides_presets() {
+        let mut args = BUILD.to_vec();
+        args.extend(["--module-size", "3"]);
+        assert!(added("module-size = 8", &args).unwrap().is_empty());
+
+        // --width cant be used with --module-size, so the preset's module size is left out
+        let mut args = BUILD.to_vec();
+        args.extend(["--width", "400"]);
+        assert!(added("module-size = 8", &args).unwrap().is_empty());
+    }
+
+    #[test]
+    fn options_other_subcommands_have_are_skipped() {
+        let preview = ["preview", "5", "https://e.co", "in.png"];
+        let added = added("dot-patterns = true\nthreshold = 90", &preview).unwrap();
+        assert_eq!(added, ["--threshold=90"]);
+    }
+
+    #[test]
+    fn unknown_options_are_errors() {
+        assert!(added("colour = \"red\"", &BUILD).is_err());
+        assert!(added("dot-patterns = 3", &BUILD).is_err());
+    }
+}
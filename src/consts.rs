@@ -177,8 +177,47 @@ pub fn pattern_locations(v: Version) -> &'static [u32] {
     &row[..n]
 }
 
-/// # For error correction level L, and mask pattern 1
-pub const FORMAT_STRING: [u8; 15] = [1, 1, 1, 0, 0, 1, 0, 1, 1, 1, 1, 0, 0, 1, 1];
+/// The 15 bit format information for an error correction level (L is 1) and mask pattern, with bch error correction
+/// see https://www.thonky.com/qr-code-tutorial/format-version-information
+pub const fn format_bits(level: u16, mask: u8) -> u16 {
+    let data = (level << 3) | mask as u16;
+    let mut remainder = data << 10;
+    let mut bit = 14;
+    while bit >= 10 {
+        if remainder & (1 << bit) != 0 {
+            remainder ^= 0x537 << (bit - 10);
+        }
+        bit -= 1;
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+/// # For error correction level L, most significant bit first
+pub const fn format_string(mask: u8) -> [u8; 15] {
+    let bits = format_bits(1, mask);
+    let mut string = [0; 15];
+    let mut i = 0;
+    while i < 15 {
+        string[i] = ((bits >> (14 - i)) & 1) as u8;
+        i += 1;
+    }
+    string
+}
+
+/// Whether mask pattern 0-7 flips the module at (x, y)
+pub const fn mask_bit(mask: u8, x: usize, y: usize) -> bool {
+    let (i, j) = (y, x);
+    match mask {
+        0 => (i + j) % 2 == 0,
+        1 => i % 2 == 0,
+        2 => j % 3 == 0,
+        3 => (i + j) % 3 == 0,
+        4 => (i / 2 + j / 3) % 2 == 0,
+        5 => (i * j) % 2 + (i * j) % 3 == 0,
+        6 => ((i * j) % 2 + (i * j) % 3) % 2 == 0,
+        _ => ((i + j) % 2 + (i * j) % 3) % 2 == 0,
+    }
+}
 
 const VERSION_STRINGS: [[u8; 18]; 34] = [
         [0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 1, 0, 0, 1, 0, 1, 0, 0],
@@ -226,14 +265,3 @@ pub const fn versions_string(v: Version) -> [u8; 18] {
 pub const fn total_blocks(v: Version) -> usize {
     number_of_blocks(v, 1) + number_of_blocks(v, 2)
 }
-
-const CHAR_CAPACITIES: [usize; 40] = [
-    41, 77, 127, 187, 255, 322, 370, 461, 552, 652, 772, 883, 1022, 1101, 1250, 1408, 1548,
-    1725, 1903, 2061, 2232, 2409, 2620, 2812, 3057, 3283, 3517, 3669, 3909, 4158, 4417, 4686,
-    4965, 5253, 5529, 5836, 6153, 6479, 6743, 7089,
-];
-
-/// For error correction level L, all versions (1-40)
-pub const fn numeric_char_capacity(v: Version) -> usize {
-    CHAR_CAPACITIES[v.idx()]
-}
@@ -34,7 +34,6 @@ impl<'a> Cursor<'a> {
         match self.next_move {
             Move::Left => {
                 if self.x != 0 && !self.code.is_open(self.x - 1, self.y) {
-                    self.code.save()?;
                     return Err(anyhow!("No valid moves! at ({},{})", self.x, self.y));
                 }
                 self.x -= 1;
@@ -91,7 +90,6 @@ impl<'a> Cursor<'a> {
                     self.next_move = Move::Left;
                     self.prev_move = Move::Left;
                 } else {
-                    self.code.save()?;
                     return Err(anyhow!("No valid moves! at ({},{})", self.x, self.y));
                 }
             }
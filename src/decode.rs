@@ -0,0 +1,830 @@
+// a decoder for codes with error correction level L, used to check that codes still scan after being degraded.
+// finds the three finder patterns, samples the module grid through a perspective transform, and corrects each
+// block with reed solomon decoding
+// see https://www.thonky.com/qr-code-tutorial/format-version-information & https://en.wikiversity.org/wiki/Reed%E2%80%93Solomon_codes_for_coders
+
+use crate::consts::{self, Version};
+use crate::gf::Field;
+use crate::layout;
+use crate::target;
+use anyhow::{anyhow, Context};
+use image::GrayImage;
+
+pub struct Decoded {
+    pub version: u8,
+    // mask pattern, 0-7
+    pub mask: u8,
+    // true for dark modules, as they appear in the image
+    pub modules: Vec<Vec<bool>>,
+    // the average side length of a module in the image, in pixels
+    pub module_size: f64,
+    pub text: String,
+}
+
+pub fn decode(image: &GrayImage) -> anyhow::Result<Decoded> {
+    let threshold = target::otsu(image.pixels().map(|p| p.0[0]));
+    let dark = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && (x as u32) < image.width()
+            && (y as u32) < image.height()
+            && image.get_pixel(x as u32, y as u32).0[0] < threshold
+    };
+
+    let finders = find_finders(image.width() as i64, image.height() as i64, &dark);
+    let [top_left, top_right, bottom_left] =
+        choose_finders(&finders).context("Could not find the finder patterns")?;
+    let module_size = (top_left.module + top_right.module + bottom_left.module) / 3.0;
+
+    // the distance between the finder pattern centers is the side length minus 7 modules
+    let across = (distance(top_left, top_right) + distance(top_left, bottom_left)) / 2.0;
+    let estimate = ((across / module_size + 7.0 - 17.0) / 4.0).round() as i32;
+
+    let mut error = anyhow!("Could not estimate the version of the code");
+    for offset in [0, 1, -1, 2, -2] {
+        let Ok(version) = Version::new((estimate + offset).clamp(0, 255) as u8) else {
+            continue;
+        };
+        let side_len = consts::side_len_of_version(version) as f64;
+
+        // map module coordinates to the image, with the finder pattern centers 3.5 modules in from the corners.
+        // the bottom right corner starts out as if the code was a parallelogram
+        let near = 3.5;
+        let far = side_len - 3.5;
+        let mut from = vec![(near, near), (far, near), (near, far), (far, far)];
+        let mut to = vec![
+            (top_left.x, top_left.y),
+            (top_right.x, top_right.y),
+            (bottom_left.x, bottom_left.y),
+            (
+                top_right.x + bottom_left.x - top_left.x,
+                top_right.y + bottom_left.y - top_left.y,
+            ),
+        ];
+        let Some(mut transform) = Homography::new(&from, &to) else {
+            continue;
+        };
+        // the guess is replaced by the alignment patterns that are found
+        from.pop();
+        to.pop();
+
+        // correct for perspective with the alignment patterns, starting with the ones closest to the top left finder
+        // pattern where the guess is the most accurate, and refitting the transform after each one is found
+        let locations = consts::pattern_locations(version);
+        let last = *locations.last().unwrap_or(&0);
+        let mut alignments = locations
+            .iter()
+            .flat_map(|row| locations.iter().map(move |col| (*col, *row)))
+            .filter(|position| ![(6, 6), (6, last), (last, 6)].contains(position))
+            .collect::<Vec<_>>();
+        alignments.sort_by_key(|(col, row)| col + row);
+        for (col, row) in alignments {
+            let center = (col as f64 + 0.5, row as f64 + 0.5);
+            if let Some(found) = find_alignment(&transform, center, module_size * 3.0, &dark) {
+                from.push(center);
+                to.push(found);
+                transform = Homography::new(&from, &to).unwrap_or(transform);
+            }
+        }
+
+        let modules = sample(&transform, version, image, threshold);
+        match read_modules(&modules, version) {
+            Ok((mask, text)) => {
+                return Ok(Decoded {
+                    version: version.get(),
+                    mask,
+                    modules,
+                    module_size,
+                    text,
+                })
+            }
+            // the error from the estimated version is the most useful one to report
+            Err(e) if offset == 0 => error = e,
+            Err(_) => (),
+        }
+    }
+
+    Err(error)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Finder {
+    x: f64,
+    y: f64,
+    module: f64,
+    // the number of scan lines that found this finder pattern
+    count: usize,
+}
+
+fn distance(a: Finder, b: Finder) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+// run lengths close to the 1:1:3:1:1 ratio of a finder pattern, returns the size of a module
+fn finder_ratio(runs: &[usize; 5]) -> Option<f64> {
+    let total: usize = runs.iter().sum();
+    if total < 7 {
+        return None;
+    }
+    let unit = total as f64 / 7.0;
+    let ratios = [1.0, 1.0, 3.0, 1.0, 1.0];
+
+    runs.iter()
+        .zip(ratios)
+        .all(|(run, ratio)| (*run as f64 - unit * ratio).abs() < unit * ratio.min(2.0) / 2.0 + 0.5)
+        .then_some(unit)
+}
+
+// walks outwards from a dark pixel along a line, and checks that the runs around it look like a finder pattern.
+// returns the position of the center along the line and the size of a module
+fn cross_check(dark: impl Fn(i64) -> bool, center: i64, limit: i64) -> Option<(f64, f64)> {
+    if !dark(center) {
+        return None;
+    }
+
+    let mut runs = [0; 5];
+    let mut i = center;
+    for (run, color) in [(2, true), (1, false), (0, true)] {
+        while i >= 0 && dark(i) == color {
+            runs[run] += 1;
+            i -= 1;
+        }
+    }
+
+    let mut j = center + 1;
+    for (run, color) in [(2, true), (3, false), (4, true)] {
+        while j < limit && dark(j) == color {
+            runs[run] += 1;
+            j += 1;
+        }
+    }
+
+    let module = finder_ratio(&runs)?;
+    let center = j as f64 - runs[4] as f64 - runs[3] as f64 - runs[2] as f64 / 2.0;
+    Some((center, module))
+}
+
+fn find_finders(width: i64, height: i64, dark: &impl Fn(i64, i64) -> bool) -> Vec<Finder> {
+    let mut finders: Vec<Finder> = Vec::new();
+
+    for y in 0..height {
+        // (start, length, dark) of each run of same colored pixels
+        let mut runs: Vec<(i64, usize, bool)> = Vec::new();
+        for x in 0..width {
+            match runs.last_mut() {
+                Some(run) if run.2 == dark(x, y) => run.1 += 1,
+                _ => runs.push((x, 1, dark(x, y))),
+            }
+        }
+
+        for window in runs.windows(5) {
+            let lengths = [
+                window[0].1,
+                window[1].1,
+                window[2].1,
+                window[3].1,
+                window[4].1,
+            ];
+            if !window[0].2 || finder_ratio(&lengths).is_none() {
+                continue;
+            }
+
+            // confirm the pattern vertically, then again horizontally through the new center
+            let x = window[2].0 + window[2].1 as i64 / 2;
+            let Some((center_y, vertical)) = cross_check(|i| dark(x, i), y, height) else {
+                continue;
+            };
+            let row = center_y as i64;
+            let Some((center_x, horizontal)) = cross_check(|i| dark(i, row), x, width) else {
+                continue;
+            };
+
+            let found = Finder {
+                x: center_x,
+                y: center_y,
+                module: (vertical + horizontal) / 2.0,
+                count: 1,
+            };
+
+            // merge with finder patterns that were already found on earlier lines
+            match finders.iter_mut().find(|f| {
+                (f.x - found.x).abs() <= f.module * 2.0
+                    && (f.y - found.y).abs() <= f.module * 2.0
+                    && (f.module - found.module).abs() <= f.module.max(1.0)
+            }) {
+                Some(f) => {
+                    let n = f.count as f64;
+                    f.x = (f.x * n + found.x) / (n + 1.0);
+                    f.y = (f.y * n + found.y) / (n + 1.0);
+                    f.module = (f.module * n + found.module) / (n + 1.0);
+                    f.count += 1;
+                }
+                None => finders.push(found),
+            }
+        }
+    }
+
+    finders
+}
+
+// picks the three finder patterns that are most likely to be the corners of the code, ordered as top left,
+// top right and bottom left
+fn choose_finders(finders: &[Finder]) -> Option<[Finder; 3]> {
+    let mut candidates = finders.to_vec();
+    candidates.sort_by_key(|f| std::cmp::Reverse(f.count));
+    candidates.truncate(10);
+    let most = candidates.first()?.count as f64;
+
+    let mut best: Option<(f64, [Finder; 3])> = None;
+    for i in 0..candidates.len() {
+        for j in (i + 1)..candidates.len() {
+            for k in (j + 1)..candidates.len() {
+                let triple = [candidates[i], candidates[j], candidates[k]];
+
+                // the corner with the right angle is opposite the longest side
+                let mut sides = [
+                    (distance(triple[1], triple[2]), 0),
+                    (distance(triple[0], triple[2]), 1),
+                    (distance(triple[0], triple[1]), 2),
+                ];
+                sides.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let [(a, _), (b, _), (c, corner)] = sides;
+                if a < 1.0 {
+                    continue;
+                }
+
+                let modules = triple.map(|f| f.module);
+                let mean = modules.iter().sum::<f64>() / 3.0;
+                let spread = modules.iter().map(|m| (m - mean).abs()).sum::<f64>() / mean;
+                // real finder patterns are found on about as many scan lines as each other, and finder-like shapes in the
+                // image usually arent
+                let fewest = triple.iter().map(|f| f.count).min().unwrap_or(0) as f64;
+                let score = (c * c - a * a - b * b).abs() / (c * c)
+                    + (b - a) / b
+                    + spread
+                    + (1.0 - fewest / most);
+
+                if best.is_none_or(|(s, _)| score < s) {
+                    let top_left = triple[corner];
+                    let mut others = triple
+                        .iter()
+                        .enumerate()
+                        .filter(|(n, _)| *n != corner)
+                        .map(|(_, f)| *f);
+                    let (mut right, mut bottom) = (others.next()?, others.next()?);
+
+                    // with y pointing down, the top right pattern is clockwise from the bottom left one
+                    let cross = (right.x - top_left.x) * (bottom.y - top_left.y)
+                        - (right.y - top_left.y) * (bottom.x - top_left.x);
+                    if cross < 0.0 {
+                        std::mem::swap(&mut right, &mut bottom);
+                    }
+                    best = Some((score, [top_left, right, bottom]));
+                }
+            }
+        }
+    }
+
+    best.map(|(_, finders)| finders)
+}
+
+// searches up to reach pixels around where the alignment pattern centered at module coordinates center should be,
+// for the spot that looks the most like a dark module surrounded by a light ring and a dark ring
+fn find_alignment(
+    transform: &Homography,
+    center: (f64, f64),
+    reach: f64,
+    dark: &impl Fn(i64, i64) -> bool,
+) -> Option<(f64, f64)> {
+    let (cx, cy) = center;
+    let (px, py) = transform.map(cx, cy);
+    let origin = transform.map(cx - 1.0, cy - 1.0);
+    let right = transform.map(cx + 1.0, cy - 1.0);
+    let down = transform.map(cx - 1.0, cy + 1.0);
+    let step_x = ((right.0 - origin.0) / 2.0, (right.1 - origin.1) / 2.0);
+    let step_y = ((down.0 - origin.0) / 2.0, (down.1 - origin.1) / 2.0);
+
+    let reach = reach.ceil() as i64;
+    let mut best = (0, Vec::new());
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let (x, y) = (px + dx as f64, py + dy as f64);
+            let mut score = 0;
+            for j in -2i32..=2 {
+                for i in -2i32..=2 {
+                    let sx = x + i as f64 * step_x.0 + j as f64 * step_y.0;
+                    let sy = y + i as f64 * step_x.1 + j as f64 * step_y.1;
+                    let expected = i.abs().max(j.abs()) != 1;
+                    score += (dark(sx.floor() as i64, sy.floor() as i64) == expected) as i32;
+                }
+            }
+            if score > best.0 {
+                best = (score, vec![(x, y)]);
+            } else if score == best.0 {
+                best.1.push((x, y));
+            }
+        }
+    }
+
+    // allow a few modules to be wrong, the image can cover part of the pattern
+    if best.0 < 21 {
+        return None;
+    }
+    let n = best.1.len() as f64;
+    let x = best.1.iter().map(|p| p.0).sum::<f64>() / n;
+    let y = best.1.iter().map(|p| p.1).sum::<f64>() / n;
+    Some((x, y))
+}
+
+// reads the color of each module from the average of a few pixels around its center
+fn sample(
+    transform: &Homography,
+    version: Version,
+    image: &GrayImage,
+    threshold: u8,
+) -> Vec<Vec<bool>> {
+    let side_len = consts::side_len_of_version(version) as usize;
+    let offsets = [
+        (0.0, 0.0),
+        (-0.2, -0.2),
+        (0.2, -0.2),
+        (-0.2, 0.2),
+        (0.2, 0.2),
+    ];
+
+    (0..side_len)
+        .map(|y| {
+            (0..side_len)
+                .map(|x| {
+                    let sum: u32 = offsets
+                        .iter()
+                        .map(|(ox, oy)| {
+                            let (px, py) = transform.map(x as f64 + 0.5 + ox, y as f64 + 0.5 + oy);
+                            let px = (px.max(0.0) as u32).min(image.width() - 1);
+                            let py = (py.max(0.0) as u32).min(image.height() - 1);
+                            image.get_pixel(px, py).0[0] as u32
+                        })
+                        .sum();
+                    sum < threshold as u32 * offsets.len() as u32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// reads the format information, unmasks and corrects the data, and returns the mask pattern and the encoded text
+fn read_modules(modules: &[Vec<bool>], version: Version) -> anyhow::Result<(u8, String)> {
+    let side_len = modules.len();
+
+    // both copies of the format information, most significant bit first, placed the same way CodeImg does
+    let mut near = 0u16;
+    let mut far = 0u16;
+    for i in 0..15 {
+        let (nx, ny) = match i {
+            0..=5 => (i, 8),
+            6 => (7, 8),
+            7 | 8 => (8, 15 - i),
+            _ => (8, 14 - i),
+        };
+        let (fx, fy) = match i {
+            0..=6 => (8, side_len - 1 - i),
+            _ => (side_len - 15 + i, 8),
+        };
+        near = (near << 1) | modules[ny][nx] as u16;
+        far = (far << 1) | modules[fy][fx] as u16;
+    }
+
+    let (level, mask) = (0..4)
+        .flat_map(|level| (0..8u8).map(move |mask| (level, mask)))
+        .map(|(level, mask)| {
+            let bits = consts::format_bits(level, mask);
+            let errors = (bits ^ near).count_ones().min((bits ^ far).count_ones());
+            (errors, level, mask)
+        })
+        .min()
+        .filter(|(errors, _, _)| *errors <= 3)
+        .map(|(_, level, mask)| (level, mask))
+        .context("Could not read the format information")?;
+
+    // level L is 01
+    if level != 1 {
+        return Err(anyhow!(
+            "Only codes with error correction level L can be decoded"
+        ));
+    }
+
+    // read the codewords in the same zigzag order that Cursor places them
+    let patterns = layout::function_patterns(version);
+    let mut bits = Vec::with_capacity(side_len * side_len);
+    let mut x = side_len as i64 - 1;
+    let mut upwards = true;
+    while x > 0 {
+        if x == 6 {
+            x -= 1;
+        }
+        for n in 0..side_len {
+            let y = if upwards { side_len - 1 - n } else { n };
+            for col in [x as usize, x as usize - 1] {
+                if patterns[y][col].is_none() {
+                    bits.push(modules[y][col] ^ consts::mask_bit(mask, col, y));
+                }
+            }
+        }
+        upwards = !upwards;
+        x -= 2;
+    }
+    let codewords = bits
+        .chunks_exact(8)
+        .map(|byte| byte.iter().fold(0u8, |v, bit| (v << 1) | *bit as u8))
+        .collect::<Vec<_>>();
+
+    // undo the interleaving of the blocks
+    let ec_len = consts::ec_bytes_per_block(version);
+    let mut block_lens = Vec::new();
+    for group in 1..=consts::number_of_groups(version) as u32 {
+        for _ in 0..consts::number_of_blocks(version, group) {
+            block_lens.push(consts::data_bytes_per_block(version, group));
+        }
+    }
+    let mut blocks = block_lens
+        .iter()
+        .map(|len| Vec::with_capacity(len + ec_len))
+        .collect::<Vec<Vec<u8>>>();
+    let mut codewords = codewords.into_iter();
+    let longest = *block_lens.iter().max().unwrap_or(&0);
+    for i in 0..longest {
+        for (block, len) in blocks.iter_mut().zip(&block_lens) {
+            if i < *len {
+                block.push(codewords.next().context("Code is missing codewords")?);
+            }
+        }
+    }
+    for _ in 0..ec_len {
+        for block in blocks.iter_mut() {
+            block.push(codewords.next().context("Code is missing codewords")?);
+        }
+    }
+
+    let field = Field::new();
+    let mut data = Vec::with_capacity(consts::required_data_bits(version) / 8);
+    for (block, len) in blocks.iter_mut().zip(&block_lens) {
+        correct(&field, block, ec_len)?;
+        data.extend_from_slice(&block[..*len]);
+    }
+
+    Ok((mask, parse(&data, version)?))
+}
+
+fn mul(field: &Field, a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        field.exp()[field.log()[a as usize] as usize + field.log()[b as usize] as usize]
+    }
+}
+
+fn div(field: &Field, a: u8, b: u8) -> u8 {
+    if a == 0 {
+        0
+    } else {
+        field.exp()[field.log()[a as usize] as usize + 255 - field.log()[b as usize] as usize]
+    }
+}
+
+// evaluates a polynomial stored lowest degree first
+fn eval(field: &Field, poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0, |v, c| mul(field, v, x) ^ c)
+}
+
+// corrects up to ec_len / 2 wrong codewords in a block in place, with the berlekamp massey algorithm
+fn correct(field: &Field, block: &mut [u8], ec_len: usize) -> anyhow::Result<()> {
+    let n = block.len();
+
+    // the block is a polynomial with the first codeword as the highest degree, and the generator's roots are a^0..a^ec_len
+    let syndromes = (0..ec_len)
+        .map(|i| {
+            block
+                .iter()
+                .fold(0, |v, c| mul(field, v, field.exp()[i]) ^ c)
+        })
+        .collect::<Vec<_>>();
+    if syndromes.iter().all(|s| *s == 0) {
+        return Ok(());
+    }
+
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    let mut errors = 0;
+    let mut shift = 1;
+    let mut previous_discrepancy = 1;
+    for step in 0..ec_len {
+        let mut discrepancy = syndromes[step];
+        for i in 1..=errors.min(locator.len() - 1) {
+            discrepancy ^= mul(field, locator[i], syndromes[step - i]);
+        }
+
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let coef = div(field, discrepancy, previous_discrepancy);
+        let mut next = locator.clone();
+        next.resize(next.len().max(previous.len() + shift), 0);
+        for (i, p) in previous.iter().enumerate() {
+            next[i + shift] ^= mul(field, coef, *p);
+        }
+
+        if 2 * errors <= step {
+            previous = std::mem::replace(&mut locator, next);
+            errors = step + 1 - errors;
+            previous_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            locator = next;
+            shift += 1;
+        }
+    }
+
+    if errors * 2 > ec_len {
+        return Err(anyhow!("Too many errors to correct"));
+    }
+
+    // the evaluator is the syndromes times the locator, cut off at ec_len terms
+    let mut evaluator = vec![0u8; ec_len];
+    for (i, s) in syndromes.iter().enumerate() {
+        for (j, l) in locator.iter().enumerate() {
+            if i + j < ec_len {
+                evaluator[i + j] ^= mul(field, *s, *l);
+            }
+        }
+    }
+    // the formal derivative keeps the odd terms
+    let derivative = locator
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, l)| if i % 2 == 1 { *l } else { 0 })
+        .collect::<Vec<_>>();
+
+    let mut found = 0;
+    for (position, codeword) in block.iter_mut().enumerate() {
+        let power = n - 1 - position;
+        let inverse = field.exp()[(255 - power % 255) % 255];
+        if eval(field, &locator, inverse) != 0 {
+            continue;
+        }
+        let magnitude = mul(
+            field,
+            field.exp()[power % 255],
+            div(
+                field,
+                eval(field, &evaluator, inverse),
+                eval(field, &derivative, inverse),
+            ),
+        );
+        *codeword ^= magnitude;
+        found += 1;
+    }
+
+    if found != errors {
+        return Err(anyhow!("Could not locate the errors in a block"));
+    }
+    Ok(())
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() * 8 - self.pos
+    }
+
+    fn read(&mut self, n: usize) -> anyhow::Result<u32> {
+        if n > self.remaining() {
+            return Err(anyhow!("Data ended in the middle of a segment"));
+        }
+        let mut v = 0;
+        for _ in 0..n {
+            let bit = (self.bytes[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            v = (v << 1) | bit as u32;
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+}
+
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+// reads the numeric, alphanumeric and byte mode segments of the data
+fn parse(data: &[u8], version: Version) -> anyhow::Result<String> {
+    let mut reader = BitReader {
+        bytes: data,
+        pos: 0,
+    };
+    let mut text = String::new();
+
+    while reader.remaining() >= 4 {
+        match reader.read(4)? {
+            0b0000 => break,
+            0b0001 => {
+                let count = reader.read(consts::char_count_indicator_len_num(version))? as usize;
+                for group in (0..count).step_by(3) {
+                    let (bits, digits) = match count - group {
+                        1 => (4, 1),
+                        2 => (7, 2),
+                        _ => (10, 3),
+                    };
+                    let value = reader.read(bits)?;
+                    if value >= 10u32.pow(digits as u32) {
+                        return Err(anyhow!(
+                            "Numeric group {value} does not fit in {digits} digits"
+                        ));
+                    }
+                    text.push_str(&format!("{:0digits$}", value));
+                }
+            }
+            0b0010 => {
+                let bits = match version.get() {
+                    1..=9 => 9,
+                    10..=26 => 11,
+                    _ => 13,
+                };
+                let count = reader.read(bits)? as usize;
+                for pair in (0..count).step_by(2) {
+                    let chars = if count - pair == 1 {
+                        vec![reader.read(6)? as usize]
+                    } else {
+                        let v = reader.read(11)? as usize;
+                        vec![v / 45, v % 45]
+                    };
+                    for c in chars {
+                        text.push(
+                            *ALPHANUMERIC
+                                .get(c)
+                                .context("Invalid alphanumeric character")?
+                                as char,
+                        );
+                    }
+                }
+            }
+            0b0100 => {
+                let count = reader.read(consts::char_count_indicator_len_byte(version))?;
+                for _ in 0..count {
+                    // byte mode is ISO 8859-1, which maps directly to the first 256 chars
+                    text.push(reader.read(8)? as u8 as char);
+                }
+            }
+            mode => return Err(anyhow!("Unsupported mode {:04b}", mode)),
+        }
+    }
+
+    Ok(text)
+}
+
+// a perspective transform fitted to 4 or more pairs of points, exact for 4 and least squares for more
+// https://en.wikipedia.org/wiki/Homography_(computer_vision)
+pub(crate) struct Homography([f64; 8]);
+
+impl Homography {
+    pub(crate) fn new(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Self> {
+        if from.len() < 4 || from.len() != to.len() {
+            return None;
+        }
+
+        // x = (a*u + b*v + c) / (g*u + h*v + 1), and the same for y with d, e and f.
+        // each pair of points gives two equations, which are summed into the normal equations
+        let mut rows = [[0.0; 9]; 8];
+        for ((u, v), (x, y)) in from.iter().zip(to.iter()) {
+            for equation in [
+                [*u, *v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, *x],
+                [0.0, 0.0, 0.0, *u, *v, 1.0, -u * y, -v * y, *y],
+            ] {
+                for (i, row) in rows.iter_mut().enumerate() {
+                    for (value, e) in row.iter_mut().zip(equation) {
+                        *value += equation[i] * e;
+                    }
+                }
+            }
+        }
+
+        // gaussian elimination with partial pivoting
+        for col in 0..8 {
+            let pivot =
+                (col..8).max_by(|a, b| rows[*a][col].abs().total_cmp(&rows[*b][col].abs()))?;
+            if rows[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            rows.swap(col, pivot);
+            for row in 0..8 {
+                if row != col {
+                    let factor = rows[row][col] / rows[col][col];
+                    let pivot_row = rows[col];
+                    for (value, p) in rows[row].iter_mut().zip(pivot_row) {
+                        *value -= factor * p;
+                    }
+                }
+            }
+        }
+
+        Some(Homography(std::array::from_fn(|i| rows[i][8] / rows[i][i])))
+    }
+
+    pub(crate) fn map(&self, u: f64, v: f64) -> (f64, f64) {
+        let [a, b, c, d, e, f, g, h] = self.0;
+        let w = g * u + h * v + 1.0;
+        ((a * u + b * v + c) / w, (d * u + e * v + f) / w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gf;
+    use crate::qr;
+    use crate::style::PatternStyle;
+    use image::{DynamicImage, RgbImage};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::rc::Rc;
+
+    #[test]
+    fn built_codes_decode_to_their_url() {
+        let target = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            let v = if (x / 16 + y / 16) % 2 == 0 { 40 } else { 220 };
+            image::Rgb([v, v, v])
+        }));
+        for (version, url) in [(2, "https://e.co"), (7, "https://example.com/123456")] {
+            let options = qr::Options {
+                module_size: 3,
+                seed: Some(1),
+                ..Default::default()
+            };
+            let output = qr::build(version, url.to_string(), target.clone(), &options).unwrap();
+            let decoded = decode(&DynamicImage::ImageRgba8(output.image).to_luma8()).unwrap();
+            assert_eq!(decoded.version, version);
+            // the numbers after the # are chosen by the solver
+            let (text, numbers) = decoded.text.split_once('#').unwrap();
+            assert_eq!(text, url);
+            assert!(numbers.bytes().all(|c| c.is_ascii_digit()));
+            assert_eq!(Some(decoded.modules), output.modules);
+        }
+    }
+
+    #[test]
+    fn codes_with_dotted_patterns_decode() {
+        let target = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| {
+            let v = if x < 32 { 30 } else { 230 };
+            image::Rgb([v, v, v])
+        }));
+        let options = qr::Options {
+            module_size: 6,
+            seed: Some(1),
+            patterns: PatternStyle::Dots {
+                min_core: 1,
+                finders: false,
+            },
+            ..Default::default()
+        };
+        let output = qr::build(7, "https://e.co".to_string(), target, &options).unwrap();
+        let decoded = decode(&DynamicImage::ImageRgba8(output.image).to_luma8()).unwrap();
+        assert!(decoded.text.starts_with("https://e.co#"));
+    }
+
+    #[test]
+    fn correct_fixes_up_to_half_of_the_ec_codewords() {
+        let field = Rc::new(Field::new());
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for (data_len, ec_len) in [(19, 7), (55, 20), (97, 30)] {
+            let data = (0..data_len).map(|_| rng.gen()).collect::<Vec<u8>>();
+            let poly = gf::gen_poly(Rc::clone(&field), ec_len);
+            let mut block = data.clone();
+            block.extend(gf::ec_codewords(Rc::clone(&field), &data, &poly));
+
+            for errors in 0..=ec_len / 2 + 1 {
+                let mut damaged = block.clone();
+                let mut positions = (0..block.len()).collect::<Vec<_>>();
+                positions.shuffle(&mut rng);
+                for position in positions.into_iter().take(errors) {
+                    damaged[position] ^= rng.gen_range(1..=255);
+                }
+
+                let result = correct(&field, &mut damaged, ec_len);
+                if errors <= ec_len / 2 {
+                    assert!(result.is_ok(), "{errors} errors with {ec_len} ec codewords");
+                    assert_eq!(damaged, block);
+                } else {
+                    assert!(
+                        result.is_err(),
+                        "{errors} errors with {ec_len} ec codewords"
+                    );
+                }
+            }
+        }
+    }
+}
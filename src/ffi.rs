@@ -0,0 +1,378 @@
+// a c abi for calling the library from other languages. options are built up with setters on an opaque handle, and
+// the finished code is another handle that owns its buffers until it is freed. every function that can fail returns a
+// QartError, and the message of the last error on the calling thread can be read with qart_last_error_message.
+// the header in ffi/qart.h is generated from this file with cbindgen
+
+use crate::consts::Version;
+use crate::qr;
+use crate::target::{self, Threshold};
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QartError {
+    /// nothing went wrong
+    Ok = 0,
+    /// a pointer that was passed in was null
+    NullPointer = 1,
+    /// the version is not between 1 and 40
+    InvalidVersion = 2,
+    /// the payload was not set, is not utf-8, cant be encoded as ISO 8859-1, or is too long for the version
+    InvalidPayload = 3,
+    /// the target was not set, or its buffer does not match its width and height
+    InvalidTarget = 4,
+    /// a setting is out of range, like a module size of 0 or a width too small for the code
+    InvalidOption = 5,
+    /// the code could not be built
+    BuildFailed = 6,
+    /// the library panicked, this is a bug
+    Panic = 7,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn fail(error: QartError, message: impl ToString) -> QartError {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    error
+}
+
+pub struct QartOptions {
+    version: u8,
+    payload: Option<String>,
+    target: Option<DynamicImage>,
+    options: qr::Options,
+}
+
+pub struct QartCode {
+    side_len: u32,
+    // one byte per module, 1 is dark and 0 is light
+    modules: Vec<u8>,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    threshold: u8,
+}
+
+/// the message of the last error returned on this thread, or null if there wasnt one. the string is owned by the
+/// library and stays valid until the next call that fails on this thread
+#[no_mangle]
+pub extern "C" fn qart_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// new options with the same defaults as the command line, and no version, payload or target. free them with
+/// qart_options_free
+#[no_mangle]
+pub extern "C" fn qart_options_new() -> *mut QartOptions {
+    Box::into_raw(Box::new(QartOptions {
+        version: 0,
+        payload: None,
+        target: None,
+        options: qr::Options::default(),
+    }))
+}
+
+/// frees options made by qart_options_new, null is ignored
+///
+/// # Safety
+/// options must be null or returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_free(options: *mut QartOptions) {
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+/// the version (size) of the code, 1-40
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_version(
+    options: *mut QartOptions,
+    version: u8,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    if let Err(e) = Version::new(version) {
+        return fail(QartError::InvalidVersion, e);
+    }
+    options.version = version;
+    QartError::Ok
+}
+
+/// the url the code points to, as a nul terminated utf-8 string. it is copied
+///
+/// # Safety
+/// options must be returned by qart_options_new, and payload must be a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_payload(
+    options: *mut QartOptions,
+    payload: *const c_char,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    if payload.is_null() {
+        return fail(QartError::NullPointer, "payload is null");
+    }
+    match CStr::from_ptr(payload).to_str() {
+        Ok(payload) => {
+            options.payload = Some(payload.to_string());
+            QartError::Ok
+        }
+        Err(e) => fail(QartError::InvalidPayload, e),
+    }
+}
+
+/// the image the code will look like, as width * height * 4 bytes of rgba, row by row. it is copied
+///
+/// # Safety
+/// options must be returned by qart_options_new, and rgba must point to at least len bytes
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_target(
+    options: *mut QartOptions,
+    rgba: *const u8,
+    len: usize,
+    width: u32,
+    height: u32,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    if rgba.is_null() {
+        return fail(QartError::NullPointer, "rgba is null");
+    }
+    let buffer = std::slice::from_raw_parts(rgba, len).to_vec();
+    match target::from_rgba(buffer, width, height) {
+        Some(target) => {
+            options.target = Some(target);
+            QartError::Ok
+        }
+        None => fail(
+            QartError::InvalidTarget,
+            "target buffer does not match its width and height",
+        ),
+    }
+}
+
+/// pixels darker than this are black
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_threshold(
+    options: *mut QartOptions,
+    threshold: u8,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    options.options.threshold = Threshold::Fixed(threshold);
+    QartError::Ok
+}
+
+/// chooses the threshold from the brightness histogram of the target
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_threshold_auto(options: *mut QartOptions) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    options.options.threshold = Threshold::Otsu;
+    QartError::Ok
+}
+
+/// makes the code the same every time it is built with the same options
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_seed(options: *mut QartOptions, seed: u64) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    options.options.seed = Some(seed);
+    QartError::Ok
+}
+
+/// the side length of each module in the rgba image, in pixels
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_module_size(
+    options: *mut QartOptions,
+    module_size: u32,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    if module_size == 0 {
+        return fail(QartError::InvalidOption, "module size must be at least 1");
+    }
+    options.options.module_size = module_size;
+    QartError::Ok
+}
+
+/// the side length of the whole rgba image in pixels, instead of the module size. the modules are made as big as fits,
+/// and the pixels left over are split evenly around the code. 0 goes back to using the module size
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_width(
+    options: *mut QartOptions,
+    width: u32,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    options.options.width = (width > 0).then_some(width);
+    QartError::Ok
+}
+
+/// the width of the light margin around the code in modules, 10 by default. scanners expect at least 4
+///
+/// # Safety
+/// options must be returned by qart_options_new, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_options_set_quiet_zone(
+    options: *mut QartOptions,
+    quiet_zone: u32,
+) -> QartError {
+    let Some(options) = options.as_mut() else {
+        return fail(QartError::NullPointer, "options is null");
+    };
+    options.options.quiet_zone = quiet_zone;
+    QartError::Ok
+}
+
+/// builds the code and stores it in code, which has to be freed with qart_code_free. code is left alone on failure
+///
+/// # Safety
+/// options must be returned by qart_options_new, and code must point to a writable pointer
+#[no_mangle]
+pub unsafe extern "C" fn qart_build(
+    options: *const QartOptions,
+    code: *mut *mut QartCode,
+) -> QartError {
+    let (Some(options), false) = (options.as_ref(), code.is_null()) else {
+        return fail(QartError::NullPointer, "options or code is null");
+    };
+    let version = match Version::new(options.version) {
+        Ok(version) => version,
+        Err(e) => return fail(QartError::InvalidVersion, e),
+    };
+    let Some(payload) = options.payload.clone() else {
+        return fail(QartError::InvalidPayload, "payload was not set");
+    };
+    if let Err(e) = qr::prepare_url(payload.clone(), version) {
+        return fail(QartError::InvalidPayload, e);
+    }
+    if let Err(e) = options.options.module_size_for(version) {
+        return fail(QartError::InvalidOption, e);
+    }
+    let Some(target) = options.target.clone() else {
+        return fail(QartError::InvalidTarget, "target was not set");
+    };
+
+    // unwinding into the caller is undefined behavior, so panics are turned into an error
+    let built = panic::catch_unwind(AssertUnwindSafe(|| {
+        qr::build(options.version, payload, target, &options.options)
+    }));
+    let output = match built {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return fail(QartError::BuildFailed, format!("{:#}", e)),
+        Err(_) => {
+            return fail(
+                QartError::Panic,
+                "the library panicked while building the code",
+            )
+        }
+    };
+
+    let modules = output.modules.unwrap_or_default();
+    *code = Box::into_raw(Box::new(QartCode {
+        side_len: modules.len() as u32,
+        modules: modules.into_iter().flatten().map(u8::from).collect(),
+        width: output.image.width(),
+        height: output.image.height(),
+        rgba: output.image.into_raw(),
+        threshold: output.threshold,
+    }));
+    QartError::Ok
+}
+
+/// frees a code stored by qart_build, null is ignored
+///
+/// # Safety
+/// code must be null or stored by qart_build, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_code_free(code: *mut QartCode) {
+    if !code.is_null() {
+        drop(Box::from_raw(code));
+    }
+}
+
+/// the modules of the code, side_len * side_len bytes row by row, where 1 is dark and 0 is light. side_len is set if it
+/// isnt null. the buffer belongs to the code
+///
+/// # Safety
+/// code must be stored by qart_build, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_code_modules(code: *const QartCode, side_len: *mut u32) -> *const u8 {
+    let Some(code) = code.as_ref() else {
+        return ptr::null();
+    };
+    if let Some(side_len) = side_len.as_mut() {
+        *side_len = code.side_len;
+    }
+    code.modules.as_ptr()
+}
+
+/// the image of the code with its border, width * height * 4 bytes of rgba row by row. width and height are set if
+/// they arent null. the buffer belongs to the code
+///
+/// # Safety
+/// code must be stored by qart_build, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_code_rgba(
+    code: *const QartCode,
+    width: *mut u32,
+    height: *mut u32,
+) -> *const u8 {
+    let Some(code) = code.as_ref() else {
+        return ptr::null();
+    };
+    if let Some(width) = width.as_mut() {
+        *width = code.width;
+    }
+    if let Some(height) = height.as_mut() {
+        *height = code.height;
+    }
+    code.rgba.as_ptr()
+}
+
+/// the brightness threshold that was used, useful when it was chosen automatically
+///
+/// # Safety
+/// code must be stored by qart_build, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn qart_code_threshold(code: *const QartCode) -> u8 {
+    code.as_ref().map_or(0, |code| code.threshold)
+}
@@ -0,0 +1,102 @@
+// reading and writing the frames of animations for the animate subcommand. frames come from animated gifs and pngs, or
+// from a directory of images, and go out the same ways
+
+use anyhow::{anyhow, Context};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Delay, DynamicImage, Frame, RgbaImage};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+// the frames of an animated gif or png, or every image in a directory in order of their names, along with how long
+// each one is shown. delay is used for frames that dont say
+pub fn open(path: &str, delay: Duration) -> anyhow::Result<Vec<(DynamicImage, Duration)>> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        let mut paths = fs::read_dir(path)
+            .context("Could not read frame directory")?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Could not read frame directory")?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+        return paths
+            .iter()
+            .map(|path| {
+                let frame = image::open(path)
+                    .with_context(|| format!("Could not open frame {}", path.display()))?;
+                Ok((frame, delay))
+            })
+            .collect();
+    }
+
+    let file = || File::open(path).map(BufReader::new);
+    let frames = match extension(path).as_deref() {
+        Some("gif") => GifDecoder::new(file()?)?.into_frames().collect_frames()?,
+        Some("png" | "apng") if PngDecoder::new(file()?)?.is_apng()? => PngDecoder::new(file()?)?
+            .apng()?
+            .into_frames()
+            .collect_frames()?,
+        // anything else is a still image, which makes an animation of one frame
+        _ => return Ok(vec![(image::open(path)?, delay)]),
+    };
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let shown = Duration::from(frame.delay());
+            let shown = if shown.is_zero() { delay } else { shown };
+            (DynamicImage::ImageRgba8(frame.into_buffer()), shown)
+        })
+        .collect())
+}
+
+// saves the frames as a looping gif or animated png depending on the extension of path, or as numbered pngs in the
+// directory at path when it has no extension
+pub fn save(frames: Vec<(RgbaImage, Duration)>, path: &str) -> anyhow::Result<()> {
+    let path = Path::new(path);
+    match extension(path).as_deref() {
+        Some("gif") => {
+            let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames.into_iter().map(|(image, shown)| {
+                Frame::from_parts(image, 0, 0, Delay::from_saturating_duration(shown))
+            }))?;
+        }
+        Some("png" | "apng") => {
+            let (width, height) = frames
+                .first()
+                .map_or((1, 1), |(image, _)| image.dimensions());
+            let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            // 0 plays loops forever
+            encoder.set_animated(frames.len() as u32, 0)?;
+            let mut writer = encoder.write_header()?;
+            for (image, shown) in frames {
+                writer.set_frame_delay(shown.as_millis().min(u16::MAX as u128) as u16, 1000)?;
+                writer.write_image_data(image.as_raw())?;
+            }
+            writer.finish()?;
+        }
+        None => {
+            fs::create_dir_all(path).context("Could not create frame directory")?;
+            for (i, (image, _)) in frames.iter().enumerate() {
+                image.save(path.join(format!("frame-{:04}.png", i + 1)))?;
+            }
+        }
+        Some(extension) => {
+            return Err(anyhow!(
+                "Animations can be saved as gif or png, or to a directory, not {extension}"
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
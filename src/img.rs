@@ -1,7 +1,7 @@
 // a struct to abstract placement of individual pixels, allowing its user to only think about placement of modules
 use crate::consts::{self, Version};
-use anyhow::Context;
-use image::{ImageBuffer, Rgba};
+use crate::layout::Pattern;
+use image::{GrayImage, ImageBuffer, Rgba};
 
 pub struct CodeImg {
     img: ImageBuffer<Rgba<u8>, Vec<u8>>,
@@ -10,6 +10,8 @@ pub struct CodeImg {
     white: Rgba<u8>,
     reserved: Rgba<u8>,
     border: u32,
+    // the color each module was last filled with, so it is still known once decorate or draw_core draw over it
+    dark: Vec<Vec<bool>>,
 }
 
 impl CodeImg {
@@ -17,13 +19,15 @@ impl CodeImg {
         self.img
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        self.img.save("debug.png").context("Could not save debug code")
+    // a copy of the image as it is so far
+    pub fn snapshot(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        self.img.clone()
     }
 
     // true = black, false = white
     pub fn fill_module(&mut self, mx: u32, my: u32, val: bool) {
         let color = if val { self.black } else { self.white };
+        self.dark[my as usize][mx as usize] = val;
         for px in 0..self.module_size {
             for py in 0..self.module_size {
                 self.img.put_pixel(
@@ -53,6 +57,119 @@ impl CodeImg {
             }
         }
     }
+    // redraws alignment and timing patterns (and finder patterns if finders is set) as a dot in the center of each
+    // module, with the rest of the module following the thresholded detail image of the target
+    pub fn decorate(
+        &mut self,
+        patterns: &[Vec<Option<Pattern>>],
+        detail: &GrayImage,
+        brightness_threshold: u8,
+        min_core: u32,
+        finders: bool,
+    ) {
+        let core = self
+            .module_size
+            .div_ceil(3)
+            .max(min_core)
+            .min(self.module_size);
+        let start = (self.module_size - core) / 2;
+        let in_core = |p: u32| p >= start && p < start + core;
+
+        for (my, row) in patterns.iter().enumerate() {
+            for (mx, pattern) in row.iter().enumerate() {
+                match pattern {
+                    Some(Pattern::Alignment | Pattern::Timing) => (),
+                    Some(Pattern::Finder) if finders => (),
+                    _ => continue,
+                }
+
+                let x = mx as u32 * self.module_size;
+                let y = my as u32 * self.module_size;
+                let color = if self.is_dark(mx as u32, my as u32) {
+                    self.black
+                } else {
+                    self.white
+                };
+
+                for px in 0..self.module_size {
+                    for py in 0..self.module_size {
+                        let pixel = if in_core(px) && in_core(py) {
+                            color
+                        } else if detail.get_pixel(x + px, y + py).0[0] < brightness_threshold {
+                            self.black
+                        } else {
+                            self.white
+                        };
+                        self.img
+                            .put_pixel(self.border + x + px, self.border + y + py, pixel);
+                    }
+                }
+            }
+        }
+    }
+    // redraws a module as a square core of its color, core pixels wide, with the rest of the module showing the detail
+    // image of the target in gray. pixels of the detail on the wrong side of the threshold are brought halfway between
+    // it and the color of the module, so the whole module still reads as its color
+    pub fn draw_core(
+        &mut self,
+        mx: u32,
+        my: u32,
+        dark: bool,
+        core: u32,
+        detail: &GrayImage,
+        brightness_threshold: u8,
+    ) {
+        let color = if dark { self.black } else { self.white };
+        let clamp = |brightness: u8| {
+            if dark {
+                brightness.min(brightness_threshold / 2)
+            } else {
+                brightness.max(brightness_threshold + (255 - brightness_threshold) / 2)
+            }
+        };
+        let start = (self.module_size - core) / 2;
+        let in_core = |p: u32| p >= start && p < start + core;
+        let x = mx * self.module_size;
+        let y = my * self.module_size;
+
+        for px in 0..self.module_size {
+            for py in 0..self.module_size {
+                let pixel = if in_core(px) && in_core(py) {
+                    color
+                } else {
+                    let brightness = clamp(detail.get_pixel(x + px, y + py).0[0]);
+                    Rgba([brightness, brightness, brightness, 255])
+                };
+                self.img
+                    .put_pixel(self.border + x + px, self.border + y + py, pixel);
+            }
+        }
+    }
+    // places the format information for mask pattern 0-7 into the modules reserved for it by new
+    pub fn place_format(&mut self, mask_pattern: u8) {
+        let side_length = (self.img.width() - 2 * self.border) / self.module_size;
+        let format_string = consts::format_string(mask_pattern);
+
+        for (i, bit) in format_string[..7].iter().enumerate() {
+            let color = *bit == 1;
+            self.fill_module(8, (side_length - 1) - i as u32, color);
+            if self.is_reserved(i as u32, 8) {
+                self.fill_module(i as u32, 8, color);
+            } else {
+                self.fill_module(i as u32 + 1, 8, color);
+            }
+        }
+        for (i, bit) in format_string[7..].iter().enumerate() {
+            let color = *bit == 1;
+
+            self.fill_module((side_length - 8) + i as u32, 8, color);
+            if self.is_reserved(8, 8 - (i as u32)) {
+                self.fill_module(8, 8 - (i as u32), color);
+            } else {
+                self.fill_module(8, 8 - (i as u32 + 1), color);
+            }
+        }
+    }
     fn reserve(&mut self, mx: u32, my: u32) {
         for px in 0..self.module_size {
             for py in 0..self.module_size {
@@ -81,6 +198,10 @@ impl CodeImg {
         }
         false
     }
+    // the color the module was filled with, which doesnt change when it is decorated
+    pub fn is_dark(&self, mx: u32, my: u32) -> bool {
+        self.dark[my as usize][mx as usize]
+    }
     pub fn is_reserved(&self, mx: u32, my: u32) -> bool {
         if let Some(p) = self.img.get_pixel_checked(
             self.border + mx * self.module_size,
@@ -111,6 +232,7 @@ impl CodeImg {
             white,
             reserved,
             border,
+            dark: vec![vec![false; side_length as usize]; side_length as usize],
         };
 
         // add finder patterns + separators
@@ -182,29 +304,6 @@ impl CodeImg {
             }
         }
 
-        // place format information
-        let format_string = consts::FORMAT_STRING;
-
-        for (i, bit) in format_string[..7].iter().enumerate() {
-            let color = *bit == 1;
-            code.fill_module(8, (side_length - 1) - i as u32, color);
-            if code.is_reserved(i as u32, 8) {
-                code.fill_module(i as u32, 8, color);
-            } else {
-                code.fill_module(i as u32 + 1, 8, color);
-            }
-        }
-        for (i, bit) in format_string[7..].iter().enumerate() {
-            let color = *bit == 1;
-
-            code.fill_module((side_length - 8) + i as u32, 8, color);
-            if code.is_reserved(8, 8 - (i as u32)) {
-                code.fill_module(8, 8 - (i as u32), color);
-            } else {
-                code.fill_module(8, 8 - (i as u32 + 1), color);
-            }
-        }
-
         // place version information if applicable
         if version.get() >= 7 {
             let version_string = consts::versions_string(version);
@@ -227,3 +326,43 @@ impl CodeImg {
         code
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout;
+    use image::Luma;
+
+    #[test]
+    fn decorated_patterns_keep_their_color_in_the_core() {
+        let version = Version::new(1).unwrap();
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let mut code = CodeImg::new(6, 21, black, white, Rgba([1, 2, 3, 255]), version, 0);
+        let patterns = layout::function_patterns(version);
+        for (my, row) in patterns.iter().enumerate() {
+            for (mx, pattern) in row.iter().enumerate() {
+                if let Some(Pattern::Timing) = pattern {
+                    code.fill_module(mx as u32, my as u32, (mx + my) % 2 == 0);
+                }
+            }
+        }
+
+        // a detail image that is dark everywhere
+        let detail = GrayImage::from_pixel(126, 126, Luma([0]));
+        code.decorate(&patterns, &detail, 128, 1, false);
+
+        // the timing module at (9, 6) is light, so only its core of 2x2 pixels stays white
+        assert!(!code.is_dark(9, 6));
+        let image = code.snapshot();
+        let pixel = |x: u32, y: u32| *image.get_pixel(9 * 6 + x, 6 * 6 + y);
+        assert_eq!(pixel(2, 2), white);
+        assert_eq!(pixel(3, 3), white);
+        assert_eq!(pixel(0, 0), black);
+        assert_eq!(pixel(5, 5), black);
+
+        // finder patterns are left solid unless finders is set
+        assert!(!code.is_dark(1, 1));
+        assert_eq!(*image.get_pixel(6, 6), white);
+    }
+}
@@ -0,0 +1,67 @@
+// where the function patterns of a code are, computed from the version alone
+
+use crate::consts::{self, Version};
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    // finder patterns and their separators
+    Finder,
+    // format information and the dark module
+    Format,
+    Alignment,
+    Timing,
+    // version information, for versions 7 and up
+    Version,
+}
+
+// the function pattern each module belongs to, or none for modules that hold data or ec bits
+pub fn function_patterns(version: Version) -> Vec<Vec<Option<Pattern>>> {
+    let side_len = consts::side_len_of_version(version) as usize;
+    let mut patterns = vec![vec![None; side_len]; side_len];
+
+    // finder patterns, separators and format information
+    let far = side_len - 8;
+    fill(&mut patterns, 0..9, 0..9, Pattern::Format);
+    fill(&mut patterns, far..side_len, 0..9, Pattern::Format);
+    fill(&mut patterns, 0..9, far..side_len, Pattern::Format);
+    fill(&mut patterns, 0..8, 0..8, Pattern::Finder);
+    fill(&mut patterns, far..side_len, 0..8, Pattern::Finder);
+    fill(&mut patterns, 0..8, far..side_len, Pattern::Finder);
+
+    // alignment patterns that dont overlap the finder patterns
+    let pattern_locations = consts::pattern_locations(version);
+    for &row in pattern_locations.iter() {
+        for &col in pattern_locations.iter() {
+            let (row, col) = (row as usize, col as usize);
+            if patterns[row][col].is_none() {
+                let (x, y) = ((col - 2)..(col + 3), (row - 2)..(row + 3));
+                fill(&mut patterns, x, y, Pattern::Alignment);
+            }
+        }
+    }
+
+    // timing patterns, between the finder patterns and around the alignment patterns
+    for i in 8..far {
+        for (x, y) in [(i, 6), (6, i)] {
+            if patterns[y][x].is_none() {
+                patterns[y][x] = Some(Pattern::Timing);
+            }
+        }
+    }
+
+    // version information
+    if version.get() >= 7 {
+        let near = (side_len - 11)..(side_len - 8);
+        fill(&mut patterns, 0..6, near.clone(), Pattern::Version);
+        fill(&mut patterns, near, 0..6, Pattern::Version);
+    }
+
+    patterns
+}
+
+fn fill(patterns: &mut [Vec<Option<Pattern>>], x: Range<usize>, y: Range<usize>, pattern: Pattern) {
+    for row in patterns[y].iter_mut() {
+        row[x.clone()].fill(Some(pattern));
+    }
+}
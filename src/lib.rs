@@ -1,28 +1,183 @@
 mod arrs;
+#[cfg(any(feature = "wasm-bindgen", feature = "python"))]
+mod bindings;
 mod block;
 mod consts;
 mod cursor;
+pub mod decode;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod gf;
 mod img;
+mod layout;
+pub mod optimize;
+pub mod priority;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod report;
+pub mod sim;
+pub mod stats;
+pub mod style;
+pub mod svg;
 pub mod target;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
 
 pub mod qr {
     use crate::arrs::{Bit, BitArr, BitArrMethods, Role};
     use crate::block::Block;
     use crate::consts::{self, Version};
     use crate::cursor::Cursor;
+    use crate::decode::Decoded;
     use crate::gf::{self, Field};
     use crate::img::CodeImg;
-    use crate::target;
+    use crate::layout;
+    use crate::optimize::{self, Optimize, Variation};
+    use crate::priority::{Priority, Variance};
+    use crate::report::{BlockReport, Report};
+    use crate::stats::{Phase, Stats, Timer};
+    use crate::style::{ModuleStyle, PatternStyle};
+    use crate::target::{self, Layout, Levels, Mask, Placement, Threshold, Transparency};
     use anyhow::{Context, anyhow};
-    use image::{ImageBuffer, Rgba};
+    use image::{imageops, DynamicImage, GrayImage, ImageBuffer, Luma, Rgba};
     use rand::prelude::*;
     use std::rc::Rc;
-    use std::sync::mpsc;
+    use std::sync::Arc;
+    #[cfg(not(target_arch = "wasm32"))]
     use std::thread;
 
     const NUMBERS_ONLY: bool = false;
 
+    // settings shared by build and preview
+    #[derive(Clone, Debug)]
+    pub struct Options {
+        // the side length of each of the modules in pixels, only used by build
+        pub module_size: u32,
+        // the side length of the whole image in pixels, only used by build. when it is set, the module size is the
+        // largest one that fits instead, and the pixels left over are split evenly around the code
+        pub width: Option<u32>,
+        // the width of the light margin around the code in modules, only used by build. scanners expect at least 4
+        pub quiet_zone: u32,
+        pub threshold: Threshold,
+        pub levels: Levels,
+        // decides which modules are matched to the target first
+        pub priority: Arc<dyn Priority>,
+        // decides which modules have to match the target, and which ones dont matter
+        pub mask: Option<Mask>,
+        pub transparency: Transparency,
+        // how the target is cropped, fitted and moved onto the code
+        pub placement: Placement,
+        pub layout: Layout,
+        // how the finder, alignment and timing patterns are drawn, only used by build
+        pub patterns: PatternStyle,
+        // how the data and error correction modules are drawn, only used by build
+        pub modules: ModuleStyle,
+        // distribute uncontrollable modules randomly instead of based off of priority
+        pub random: bool,
+        // also return the layout of the code with its modules colored by block, and analyse the finished code. only used
+        // by build
+        pub debug: bool,
+        // build several candidate codes and keep the one that looks the most like the target, only used by build
+        pub optimize: Option<Optimize>,
+        // makes the output the same on every run, a random seed is used if this is none
+        pub seed: Option<u64>,
+    }
+
+    impl Default for Options {
+        fn default() -> Self {
+            Options {
+                module_size: 5,
+                width: None,
+                quiet_zone: 10,
+                threshold: Threshold::default(),
+                levels: Levels::default(),
+                priority: Arc::new(Variance),
+                mask: None,
+                transparency: Transparency::default(),
+                placement: Placement::default(),
+                layout: Layout::default(),
+                patterns: PatternStyle::default(),
+                modules: ModuleStyle::default(),
+                random: false,
+                debug: false,
+                optimize: None,
+                seed: None,
+            }
+        }
+    }
+
+    impl Options {
+        // the side length of each module in pixels for a version, chosen from the width when it is set
+        pub(crate) fn module_size_for(&self, version: Version) -> anyhow::Result<u32> {
+            let module_size = match self.width {
+                Some(width) => {
+                    let modules =
+                        consts::side_len_of_version(version) as u64 + 2 * self.quiet_zone as u64;
+                    if (width as u64) < modules {
+                        return Err(anyhow!(
+                            "a width of {width} pixels is too small for version {} with a quiet zone of {}, which \
+                             needs at least {modules}",
+                            version.get(),
+                            self.quiet_zone
+                        ));
+                    }
+                    (width as u64 / modules) as u32
+                }
+                None => self.module_size,
+            };
+            if module_size == 0 {
+                return Err(anyhow!("module size must be at least 1"));
+            }
+            Ok(module_size)
+        }
+
+        // module_size_for with the version as a number, for callers outside of the library
+        pub fn module_size_of(&self, version: u8) -> anyhow::Result<u32> {
+            self.module_size_for(Version::new(version)?)
+        }
+
+        // the side length in pixels of the image build makes for a version, including the quiet zone. it can be
+        // bigger than a u32 when the module size or quiet zone are, so it is checked before anything is drawn
+        pub fn image_side(&self, version: u8) -> anyhow::Result<u64> {
+            let version = Version::new(version)?;
+            let module_size = self.module_size_for(version)? as u64;
+            let code = (consts::side_len_of_version(version) as u64 + 2 * self.quiet_zone as u64)
+                * module_size;
+            Ok(code.max(self.width.unwrap_or(0) as u64))
+        }
+
+        // the source of all randomness used to make a code
+        fn rng(&self) -> StdRng {
+            match self.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                #[cfg(not(target_arch = "wasm32"))]
+                None => StdRng::from_entropy(),
+                // there is no os entropy in the browser, so the clock is used instead
+                #[cfg(target_arch = "wasm32")]
+                None => StdRng::seed_from_u64(
+                    web_time::SystemTime::now()
+                        .duration_since(web_time::UNIX_EPOCH)
+                        .map_or(0, |time| time.as_nanos() as u64),
+                ),
+            }
+        }
+    }
+
+    pub struct Output {
+        pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        // the brightness threshold that was used, useful when it was chosen automatically
+        pub threshold: u8,
+        // how robust the code is, only produced by build when debug is set
+        pub report: Option<Report>,
+        // how closely the code follows the target and how long each phase took, only produced by build
+        pub stats: Option<Stats>,
+        // the code before it was solved, with every module colored by its block. only produced by build when debug
+        // is set
+        pub layout: Option<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+        // whether each module is dark, row by row, only produced by build
+        pub modules: Option<Vec<Vec<bool>>>,
+    }
+
     // target length is assumed to be less than 256 chars
     // error correction is assumed to be L
     // encoding is assumed to be binary
@@ -120,6 +275,21 @@ pub mod qr {
     ) -> Vec<Block> {
         let number_of_groups = consts::number_of_groups(version);
 
+        // each group of numeric bits, which the blocks keep in range
+        let numeric_start = bits
+            .iter()
+            .position(|bit| bit.is_num())
+            .unwrap_or(bits.len());
+        let numeric_end = numeric_start
+            + bits[numeric_start..]
+                .iter()
+                .take_while(|bit| bit.is_num())
+                .count();
+        let numeric_groups = (numeric_start..numeric_end)
+            .step_by(10)
+            .map(|start| start..(start + 10).min(numeric_end))
+            .collect::<Vec<_>>();
+
         let mut blocks = Vec::with_capacity(consts::total_blocks(version));
 
         let data_bits_in_group_1 =
@@ -143,10 +313,18 @@ pub mod qr {
                     gf::ec_codewords(Rc::clone(&field), &block_bits.to_byte_arr(), generator_poly);
                 block_bits.extend_bytes(&ec_bytes, Role::EC);
 
+                // groups are given to the block their first bit is in, and cut off where the block ends
+                let block_groups = numeric_groups
+                    .iter()
+                    .filter(|group| (start..end).contains(&group.start))
+                    .map(|group| (group.start - start..group.end.min(end) - start, group.len()))
+                    .collect();
+
                 blocks.push(Block::new(
                     data_bits_per_block / 8,
                     Rc::clone(&field),
                     block_bits,
+                    block_groups,
                 ));
             }
         }
@@ -154,97 +332,366 @@ pub mod qr {
         blocks
     }
 
-    pub fn preview(
-        version: u8,
-        path: String,
-        brightness_threshold: u8,
+    // sorts modules so that the ones that matter the most to the target come first, and shuffles the ones with no weight.
+    // jitter randomly scales each weight by up to that fraction, to try out different orderings
+    fn order_modules<T>(
+        modules: &mut [T],
+        pixel: impl Fn(&T) -> target::Pixel,
         random: bool,
-    ) -> anyhow::Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-        let version = Version::new(version)?;
-
-        let side_len = crate::consts::side_len_of_version(version) as usize;
-
-        let target = target::get_target_scale(path, side_len)?;
-
-        let mut module_info = Vec::with_capacity(side_len * side_len);
-
-        for y in 0..side_len {
-            for x in 0..side_len {
-                module_info.push((x, y, target[y][x].0, target[y][x].1))
-            }
-        }
-
-        let mut rng = rand::thread_rng();
+        jitter: f32,
+        rng: &mut impl Rng,
+    ) {
         if random {
-            module_info.shuffle(&mut rng);
+            modules.shuffle(rng);
+            modules.sort_by_key(|a| std::cmp::Reverse(pixel(a).must_match()));
         } else {
-            module_info.sort_by(|a, b| a.2.cmp(&b.2).reverse());
+            modules.sort_by_cached_key(|a| {
+                let (must_match, weight) = pixel(a).order();
+                let scale = 1.0 + jitter * rng.gen_range(-1.0..=1.0);
+                // a weight that rounds down to 0 would end up in the shuffled tail with the modules that dont matter
+                let jittered = ((weight as f32 * scale) as u32).max((weight > 0) as u32);
+                std::cmp::Reverse((must_match, jittered))
+            });
 
-            let mut zero_index = module_info.len();
+            let mut zero_index = modules.len();
 
-            for i in (0..module_info.len()).rev() {
-                if module_info[i].2 != 0 {
+            for i in (0..modules.len()).rev() {
+                if pixel(&modules[i]).weight() != 0 {
                     break;
                 } else {
                     zero_index = i;
                 }
             }
 
-            module_info[zero_index..].shuffle(&mut rng);
+            modules[zero_index..].shuffle(rng);
         }
+    }
 
-        let mut result = image::ImageBuffer::new(side_len as u32, side_len as u32);
+    // shows what build will do at one pixel per module: modules that will follow the image are black or white like the
+    // target, modules that are part of the url, function patterns and format information are drawn the way build
+    // draws them, and the rest, which are decided by the solver, are gray
+    pub fn preview(
+        version: u8,
+        url: String,
+        image: DynamicImage,
+        options: &Options,
+    ) -> anyhow::Result<Output> {
+        let version = Version::new(version)?;
+        let url = prepare_url(url, version)?;
+        target::check_crop(&image, &options.placement)?;
 
-        let black = Rgba([0, 0, 0, 255]);
-        let white = Rgba([255, 255, 255, 255]);
-        let gray = Rgba([128, 128, 128, 255]);
+        let target = target::get_target_scale(image, version, 1, options);
 
-        let controllable_pixels = consts::numeric_char_capacity(version) * 3;
+        let brightness_threshold = options.threshold.resolve(&target.pixels);
+        log::info!("brightness threshold: {brightness_threshold}");
 
-        for module in module_info[0..controllable_pixels].iter() {
-            result.put_pixel(
-                module.0 as u32,
-                module.1 as u32,
-                if module.3 > brightness_threshold {
-                    white
-                } else {
-                    black
-                },
-            )
+        let variation = Variation {
+            threshold: brightness_threshold,
+            jitter: 0.0,
+            mask_pattern: 1,
+        };
+
+        let Mapped {
+            mut code,
+            blocks,
+            modules: mut module_info,
+            ..
+        } = map_modules(version, &url, &target, &variation, 1, 0, false)?;
+
+        order_modules(
+            &mut module_info,
+            |module| module.pixel,
+            options.random,
+            0.0,
+            &mut options.rng(),
+        );
+
+        // instead of solving the blocks, assume each one can set as many bits as it has bits that can be changed.
+        // this ignores bits that are decided by earlier ones, so it is a little optimistic
+        let mut free = blocks
+            .iter()
+            .map(|block| block.num_free())
+            .collect::<Vec<_>>();
+        let gray = Rgba([128, 128, 128, 255]);
+
+        for module in module_info.iter() {
+            if module.fixed {
+                code.fill_module(module.x, module.y, (module.bit == 1) == module.mask);
+            } else if !module.pixel.dont_care() && free[module.block_index] > 0 {
+                free[module.block_index] -= 1;
+                code.fill_module(module.x, module.y, module.target_color);
+            } else {
+                code.debug(module.x, module.y, gray);
+            }
         }
 
-        for module in module_info[controllable_pixels..].iter() {
-            result.put_pixel(module.0 as u32, module.1 as u32, gray)
+        Ok(Output {
+            image: code.image(),
+            threshold: brightness_threshold,
+            report: None,
+            stats: None,
+            layout: None,
+            modules: None,
+        })
+    }
+
+    // checks that the url can be encoded in the version, and adds the fragment that the numbers filling the code go
+    // into
+    pub(crate) fn prepare_url(mut url: String, version: Version) -> anyhow::Result<String> {
+        if url.chars().any(|x| x >= '\u{00FF}') {
+            return Err(anyhow!("url cannot be encoded as ISO 8859-1!"));
         }
+        url.push('#');
 
-        Ok(result)
+        // the mode and length headers of the url and the numbers, and at least one byte of numbers have to fit too
+        let count_len = consts::char_count_indicator_len_byte(version);
+        let header_bits = 4 + count_len + 4 + consts::char_count_indicator_len_num(version) + 8;
+        if url.len() >= 1 << count_len
+            || header_bits + url.len() * 8 > consts::required_data_bits(version)
+        {
+            return Err(anyhow!("url is too long for version {}", version.get()));
+        }
+        Ok(url)
     }
 
     // TODO: module size should be a const, it doesnt really matter what the module size is to the user
     pub fn build(
         version: u8,
-        mut url: String,
-        module_size: u32,
-        path: String,
-        brightness_threshold: u8,
-        random: bool,
-        debug: bool,
-    ) -> anyhow::Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-        // version, image, & url validation
-        if url.chars().any(|x| x >= '\u{00FF}') {
-            return Err(anyhow!("url cannot be encoded as ISO 8859-1!"));
+        url: String,
+        image: DynamicImage,
+        options: &Options,
+    ) -> anyhow::Result<Output> {
+        // version, size & url validation, before the image is processed
+        let checked = Version::new(version)?;
+        options.module_size_for(checked)?;
+        prepare_url(url.clone(), checked)?;
+
+        let timer = Timer::start("image");
+        log::info!("processing image...");
+        let target = prepare_target(version, image, options)?;
+        let mut phases = timer.finish();
+
+        let mut output = build_prepared(version, url, &target, options)?;
+
+        if let Some(stats) = output.stats.as_mut() {
+            phases.append(&mut stats.phases);
+            stats.phases = phases;
         }
 
+        Ok(output)
+    }
+
+    // builds a code for every frame of an animation, all with the same url and numbers that are valid in each frame.
+    // the threshold is chosen from the first frame and every frame uses the same seed. after the first frame, modules
+    // are pulled towards the color they had in the frame before by stability, see target::stabilize. optimize isnt
+    // used, since candidates with different mask patterns would change most of the modules between frames
+    pub fn animate(
+        version: u8,
+        url: String,
+        frames: Vec<DynamicImage>,
+        options: &Options,
+        stability: f32,
+    ) -> anyhow::Result<Vec<Output>> {
+        let checked = Version::new(version)?;
+        options.module_size_for(checked)?;
+        prepare_url(url.clone(), checked)?;
+        if frames.is_empty() {
+            return Err(anyhow!("an animation needs at least one frame"));
+        }
+
+        let mut options = Options {
+            seed: Some(options.seed.unwrap_or_else(|| options.rng().gen())),
+            optimize: None,
+            ..options.clone()
+        };
+        let mut outputs: Vec<Output> = Vec::with_capacity(frames.len());
+
+        for (i, frame) in frames.into_iter().enumerate() {
+            log::info!("building frame {}...", i + 1);
+            let timer = Timer::start("image");
+            let mut target = prepare_target(version, frame, &options)?;
+            match outputs.last() {
+                Some(previous) => {
+                    let modules = previous.modules.as_ref().expect("built codes have modules");
+                    target::stabilize(&mut target, modules, previous.threshold, stability);
+                }
+                None => {
+                    options.threshold = Threshold::Fixed(options.threshold.resolve(&target.pixels))
+                }
+            }
+            let mut phases = timer.finish();
+
+            let mut output = build_prepared(version, url.clone(), &target, &options)?;
+
+            if let Some(stats) = output.stats.as_mut() {
+                phases.append(&mut stats.phases);
+                stats.phases = phases;
+            }
+            outputs.push(output);
+        }
+
+        Ok(outputs)
+    }
+
+    // array of priorities and brightnesses for each pixel corresponding to the target image. it only depends on the
+    // image, the version and the options, so it can be made once and reused for codes with different urls
+    pub fn prepare_target(
+        version: u8,
+        image: DynamicImage,
+        options: &Options,
+    ) -> anyhow::Result<target::Target> {
+        let version = Version::new(version)?;
+        let module_size = options.module_size_for(version)?;
+        target::check_crop(&image, &options.placement)?;
+        Ok(target::get_target_scale(
+            image,
+            version,
+            module_size,
+            options,
+        ))
+    }
+
+    // builds a code from a target made by prepare_target, with the same version and options
+    pub fn build_prepared(
+        version: u8,
+        url: String,
+        target: &target::Target,
+        options: &Options,
+    ) -> anyhow::Result<Output> {
+        build_with_mask(version, url, target, options, 1)
+    }
+
+    // builds a code that points to url instead of the text of a code read by decode::decode, with the same version and
+    // mask pattern and the modules of the old code as the target at one pixel per module. the threshold, levels and
+    // placement in options arent used, since the target is already black and white and the size of the code
+    pub fn rebuild(url: String, decoded: &Decoded, options: &Options) -> anyhow::Result<Output> {
+        let side_len = decoded.modules.len() as u32;
+        let modules = GrayImage::from_fn(side_len, side_len, |x, y| {
+            let dark = decoded.modules[y as usize][x as usize];
+            Luma([if dark { 0 } else { 255 }])
+        });
+        let options = Options {
+            threshold: Threshold::Fixed(128),
+            levels: Levels::default(),
+            placement: Placement::default(),
+            layout: Layout::Manual,
+            ..options.clone()
+        };
+        let target = prepare_target(decoded.version, DynamicImage::ImageLuma8(modules), &options)?;
+        build_with_mask(decoded.version, url, &target, &options, decoded.mask)
+    }
+
+    // build_prepared, starting from mask_pattern. optimize may still try out other ones
+    fn build_with_mask(
+        version: u8,
+        url: String,
+        target: &target::Target,
+        options: &Options,
+        mask_pattern: u8,
+    ) -> anyhow::Result<Output> {
         let version = Version::new(version)?;
+        let url = prepare_url(url, version)?;
+        if target.pixels.len() != consts::side_len_of_version(version) as usize {
+            return Err(anyhow!("target was prepared for a different version"));
+        }
+        if let ModuleStyle::Cores { core } = options.modules {
+            if !(core > 0.0 && core <= 1.0) {
+                return Err(anyhow!(
+                    "module cores must be wider than 0 and at most 1, not {core}"
+                ));
+            }
+        }
+
+        let brightness_threshold = options.threshold.resolve(&target.pixels);
+        log::info!("brightness threshold: {brightness_threshold}");
+
+        let variation = Variation {
+            threshold: brightness_threshold,
+            jitter: 0.0,
+            mask_pattern,
+        };
+
+        let mut candidates = None;
+
+        let mut output = match options.optimize {
+            Some(settings) => {
+                let start = web_time::Instant::now();
+                let output = optimize::optimize(
+                    settings,
+                    variation,
+                    &target.pixels,
+                    &mut options.rng(),
+                    |v, rng| solve(version, &url, target, v, options, rng),
+                )?;
+                // the phases of the code that was kept are listed, and the rest of the candidates are counted together
+                let kept = output
+                    .stats
+                    .as_ref()
+                    .map_or(0.0, |stats| stats.total_time().as_secs_f64());
+                candidates = Some(Phase {
+                    name: "candidates",
+                    millis: (start.elapsed().as_secs_f64() - kept).max(0.0) * 1000.0,
+                });
+                output
+            }
+            None => {
+                let mut rng = options.rng();
+                solve(version, &url, target, &variation, options, &mut rng)?.0
+            }
+        };
+
+        if let Some(stats) = output.stats.as_mut() {
+            stats.phases.extend(candidates);
+        }
+
+        Ok(output)
+    }
+
+    // a module that holds a bit of one of the blocks
+    struct Module {
+        x: u32,
+        y: u32,
+        bit_index: usize,
+        block_index: usize,
+        target_color: bool,
+        mask: bool,
+        pixel: target::Pixel,
+        // whether the bit is part of the encoded text, which cant be changed
+        fixed: bool,
+        // the value of the bit before any of the blocks are set
+        bit: u8,
+    }
+
+    // a code with its function patterns and format information drawn, and the blocks whose bits go in the rest of it
+    struct Mapped {
+        code: CodeImg,
+        blocks: Vec<Block>,
+        // every bit of the blocks and the module it is placed in
+        modules: Vec<Module>,
+        // a copy of the code with the modules colored by block, only made when debug is set
+        layout: Option<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    }
+
+    // encodes the url, draws the function patterns and format information onto a new code image, and maps every bit of
+    // the blocks to the module it is placed in
+    fn map_modules(
+        version: Version,
+        url: &str,
+        target: &target::Target,
+        variation: &Variation,
+        module_size: u32,
+        border: u32,
+        debug: bool,
+    ) -> anyhow::Result<Mapped> {
+        let brightness_threshold = variation.threshold;
 
         let field = Rc::new(Field::new());
         // TODO: standardize the name of the generator polynomial across the crate
         let generator_poly = gf::gen_poly(Rc::clone(&field), consts::ec_bytes_per_block(version));
-        url.push('#');
 
         // data + ec encoding
         log::info!("encoding data...");
-        let data_bits = encode_chars_to_bits(version, url);
+        let data_bits = encode_chars_to_bits(version, url.to_string());
 
         let data_bytes = data_bits.to_byte_arr();
         log::debug!("{:02X?}", data_bytes);
@@ -254,7 +701,6 @@ pub mod qr {
         let black = Rgba([0, 0, 0, 255]);
         let white = Rgba([255, 255, 255, 255]);
         let reserved = Rgba([0, 0, 255, 255]);
-        let border = 10 * module_size;
         let mut code = CodeImg::new(
             module_size,
             side_length,
@@ -264,6 +710,7 @@ pub mod qr {
             version,
             border,
         );
+        code.place_format(variation.mask_pattern);
 
         let debug_colors = vec![
             Rgba([240, 75, 75, 255]),
@@ -280,29 +727,16 @@ pub mod qr {
         // TODO: when block structs are generated, all module roles are lost. if the navigator could tell which modules could not be
         // edited and put those down, instead of trying to have the block set them, it would save some time
         log::info!("generating blocks...");
-        let mut blocks = gen_blocks(data_bits, version, Rc::clone(&field), &generator_poly);
-
-        struct Module {
-            x: u32,
-            y: u32,
-            bit_index: usize,
-            block_index: usize,
-            target_color: bool,
-            mask: bool,
-            contrast: u32,
-        }
+        let blocks = gen_blocks(data_bits, version, Rc::clone(&field), &generator_poly);
 
         // TODO: data modules are added to this list, and because they are subtracted from the basis array in the block struct when it is created,
         // this results in calls to set that are apparently redundant. i tried to fix this and ended up making the code even slower, but if
         // if you are reading this and want to contribute, feel free to give it a shot
         let mut module_info = Vec::with_capacity((side_length * side_length) as usize);
 
-        log::info!("processing image...");
-        // array of contrasts and image length for each pixel corresponding to the target image
-        let target_arr = target::get_target_scale(path, side_length as usize)?;
+        let target_arr = &target.pixels;
 
-        let color = |x: usize, y: usize| (&target_arr)[y][x].1 < brightness_threshold;
-        let contrast = |x: usize, y: usize| (&target_arr)[y][x].0;
+        let color = |x: usize, y: usize| target_arr[y][x].brightness < brightness_threshold;
 
         // TODO: this block scope is clunky, this could be done better with good lifetime annotations for the block iterators
         log::info!("mapping modules...");
@@ -341,7 +775,11 @@ pub mod qr {
 
                     for _ in 0..8 {
                         if let Some((bit_index, bit)) = block_iters[block_index].next() {
-                            let mask = cursor.y % 2 == 1;
+                            let mask = !consts::mask_bit(
+                                variation.mask_pattern,
+                                cursor.x as usize,
+                                cursor.y as usize,
+                            );
 
                             sum <<= 1;
                             sum += bit;
@@ -353,7 +791,9 @@ pub mod qr {
                                 block_index,
                                 target_color: color(cursor.x as usize, cursor.y as usize),
                                 mask,
-                                contrast: contrast(cursor.x as usize, cursor.y as usize),
+                                pixel: target_arr[cursor.y as usize][cursor.x as usize],
+                                fixed: blocks[block_index].is_fixed(bit_index),
+                                bit,
                             });
 
                             if debug {
@@ -374,148 +814,125 @@ pub mod qr {
             }
 
             while cursor_result {
-                cursor.place((cursor.y + 1) % 2 == 0);
+                cursor.place(!consts::mask_bit(
+                    variation.mask_pattern,
+                    cursor.x as usize,
+                    cursor.y as usize,
+                ));
                 cursor_result = cursor.next()?
             }
         }
 
-        if debug {
-            code.save()?;
-        }
+        let layout = debug.then(|| code.snapshot());
 
-        let mut rng = rand::thread_rng();
-        if random {
-            module_info.shuffle(&mut rng);
-        } else {
-            module_info.sort_by(|a, b| a.contrast.cmp(&b.contrast).reverse());
-
-            let mut zero_index = module_info.len();
+        Ok(Mapped {
+            code,
+            blocks,
+            modules: module_info,
+            layout,
+        })
+    }
 
-            for i in (0..module_info.len()).rev() {
-                if module_info[i].contrast != 0 {
-                    break;
-                } else {
-                    zero_index = i;
-                }
-            }
+    // sets the requested bits of a block, and returns it along with the number of them that could still be set when
+    // their turn came
+    fn solve_block(mut block: Block, requests: Vec<(usize, u8)>) -> (Block, usize) {
+        let image_bits = requests
+            .into_iter()
+            .map(|(index, val)| block.set(index, val) as usize)
+            .sum();
+        block.fix_numbers();
+        (block, image_bits)
+    }
 
-            module_info[zero_index..].shuffle(&mut rng);
-        }
+    // builds a code for a target that has already been processed, and returns it along with whether each module
+    // ended up dark
+    fn solve(
+        version: Version,
+        url: &str,
+        target: &target::Target,
+        variation: &Variation,
+        options: &Options,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<(Output, Vec<Vec<bool>>)> {
+        let module_size = options.module_size_for(version)?;
+        let debug = options.debug;
+        let brightness_threshold = variation.threshold;
+
+        let mut timer = Timer::start("layout");
+        let side_length = consts::side_len_of_version(version);
+        let Mapped {
+            mut code,
+            blocks,
+            modules: mut module_info,
+            layout,
+        } = map_modules(
+            version,
+            url,
+            target,
+            variation,
+            module_size,
+            options.quiet_zone * module_size,
+            debug,
+        )?;
+
+        timer.next("solving");
+        order_modules(
+            &mut module_info,
+            |module| module.pixel,
+            options.random,
+            variation.jitter,
+            rng,
+        );
 
         // TODO: conversion between boolean and u8 is ugly and doesnt make much sense
         // the type for the color of a module should stay consistent across the entire program
         log::info!("setting module colors...");
 
-        let mut threads = Vec::with_capacity(blocks.len());
-        let mut transmitters = Vec::with_capacity(blocks.len());
-
-        // set up transmitters array, and threads with recievers
-        // move blocks into their threads
-        blocks.into_iter().for_each(|mut block| {
-            let (transmitter, receiver) = mpsc::channel();
-            transmitters.push(transmitter);
-
-            let handle = thread::spawn(move || {
-                loop {
-                    let message = receiver.recv();
-                    match message {
-                        Ok((index, val)) => {
-                            block.set(index, val);
-                        }
-                        Err(_) => break,
-                    }
-                }
-                block
+        // the bits each block is asked to set, in order. dont care modules are skipped so the solver is free to use them
+        // for the rest of the code
+        let mut requests = vec![Vec::new(); blocks.len()];
+        module_info
+            .iter()
+            .filter(|module| !module.pixel.dont_care())
+            .for_each(|module| {
+                requests[module.block_index]
+                    .push((module.bit_index, (module.target_color == module.mask) as u8))
             });
-            threads.push(handle);
-        });
-
-        // send all the indexes as messages to the threads to their respective blocks
-        module_info.iter().map(|module| {
-            transmitters[module.block_index]
-                .send((module.bit_index, (module.target_color == module.mask) as u8)).context("Could not send module indexes to threads")
-        }).collect::<anyhow::Result<Vec<_>>>()?;
 
-        // drop transmitters, sending signal to threads to break their loop
-        transmitters
+        // the blocks dont depend on each other, so each one is solved on its own thread. there are no threads in the
+        // browser, so there they are solved one after another
+        #[cfg(not(target_arch = "wasm32"))]
+        let (blocks, image_bits): (Vec<_>, Vec<_>) = blocks
             .into_iter()
-            .for_each(|transmitter| drop(transmitter));
-
-        // join all the threads and move back into blocks
-        blocks = threads
+            .zip(requests)
+            .map(|(block, requests)| thread::spawn(move || solve_block(block, requests)))
+            .collect::<Vec<_>>()
             .into_iter()
             .map(|thread| thread.join().unwrap())
+            .unzip();
+        #[cfg(target_arch = "wasm32")]
+        let (blocks, image_bits): (Vec<_>, Vec<_>) = blocks
+            .into_iter()
+            .zip(requests)
+            .map(|(block, requests)| solve_block(block, requests))
+            .unzip();
+
+        timer.next("drawing");
+        let mut block_reports = blocks
+            .iter()
+            .zip(image_bits)
+            .map(|(block, image_bits)| BlockReport {
+                data_codewords: block.num_data_bytes(),
+                ec_codewords: block.num_ec_bytes(),
+                requested_bits: 0,
+                image_bits,
+                matched_bits: 0,
+                refused_bits: block.num_refused(),
+            })
             .collect::<Vec<_>>();
 
-        let mut choice = 0;
-
-        // do while loop
-        log::info!("checking for errors...");
-        while {
-            let mut errors = Vec::new();
-
-            log::debug!("start of number correction loop");
-
-            {
-                // produces an iterator for the data bits representing numbers, in the form of (block_index, bit_index, bit)
-                let mut numeric_data_iter = blocks
-                    .iter()
-                    .enumerate()
-                    .map(|(block_index, block)| {
-                        block
-                            .iter_nums()
-                            .map(move |(bit_index, bit)| (block_index, bit_index, bit))
-                    })
-                    .flatten()
-                    .peekable();
-
-                while let Some((block_index, bit_index, bit)) = numeric_data_iter.next() {
-                    let mut indexes = Vec::with_capacity(10);
-                    indexes.push((block_index, bit_index));
-
-                    let mut val = bit as u16;
-                    let mut reached = 1;
-                    for _ in 0..9 {
-                        if let Some((block_index, bit_index, bit)) = numeric_data_iter.next() {
-                            val <<= 1;
-                            val += bit as u16;
-                            reached += 1;
-                            indexes.push((block_index, bit_index));
-                        } else {
-                            break;
-                        }
-                    }
-                    // deal with errors
-                    let compareval = match reached {
-                        10 => Ok(999),
-                        7 => Ok(99),
-                        4 => Ok(9),
-                        _ => Err(anyhow!("wrong number of numeric bits")),
-                    }?;
-
-                    match reached {
-                        10 => log::debug!("{:03}", val),
-                        7 => log::debug!("{:02}", val),
-                        4 => log::debug!("{:01}", val),
-                        _ => (),
-                    };
-
-                    if val > compareval {
-                        // TODO: make this choice between the bits to flip random
-                        choice = (choice + 1) % 5;
-
-                        errors.push(indexes[choice % indexes.len()]);
-                    }
-                }
-            }
-            errors
-                .iter()
-                .for_each(|(block_index, bit_index)| blocks[*block_index].reset(*bit_index));
-
-            log::debug!("{:?}", errors);
-
-            errors.len() != 0
-        } {}
+        let refused_bits: usize = block_reports.iter().map(|report| report.refused_bits).sum();
+        log::info!("{refused_bits} image modules refused to keep the numbers valid");
 
         if debug {
             blocks.iter().for_each(|b| b.debug());
@@ -526,14 +943,264 @@ pub mod qr {
             .map(|b| return b.ret())
             .collect::<Vec<_>>();
 
+        let mut stats = Stats {
+            controllable_modules: block_reports.iter().map(|report| report.image_bits).sum(),
+            numeric_repairs: refused_bits,
+            ..Default::default()
+        };
+        // weights of the modules that were asked to match the target, and of the ones that do
+        let (mut requested_weight, mut matched_weight) = (0u64, 0u64);
+
         module_info.iter().for_each(|module| {
-            code.fill_module(
-                module.x,
-                module.y,
-                (module_values[module.block_index][module.bit_index] == 1) == module.mask,
-            );
+            let dark = (module_values[module.block_index][module.bit_index] == 1) == module.mask;
+            code.fill_module(module.x, module.y, dark);
+
+            stats.fixed_modules += module.fixed as usize;
+            if !module.pixel.dont_care() {
+                let matched = dark == module.target_color;
+                let report = &mut block_reports[module.block_index];
+                report.requested_bits += 1;
+                report.matched_bits += matched as usize;
+                requested_weight += module.pixel.weight() as u64;
+                matched_weight += matched as u64 * module.pixel.weight() as u64;
+            }
         });
 
-        Ok(code.image())
+        let requested: usize = block_reports
+            .iter()
+            .map(|report| report.requested_bits)
+            .sum();
+        let matched: usize = block_reports.iter().map(|report| report.matched_bits).sum();
+        stats.match_rate = matched as f32 / requested.max(1) as f32;
+        stats.weighted_match_rate = matched_weight as f32 / requested_weight.max(1) as f32;
+
+        let patterns = layout::function_patterns(version);
+
+        if let (PatternStyle::Dots { min_core, finders }, Some(detail)) =
+            (options.patterns, &target.detail)
+        {
+            code.decorate(&patterns, detail, brightness_threshold, min_core, finders);
+        }
+
+        let modules = (0..side_length)
+            .map(|y| (0..side_length).map(|x| code.is_dark(x, y)).collect())
+            .collect::<Vec<Vec<_>>>();
+
+        if let (ModuleStyle::Cores { core }, Some(detail)) = (options.modules, &target.detail) {
+            let core = ((core * module_size as f32).round() as u32).clamp(1, module_size);
+            for module in module_info.iter() {
+                let dark = modules[module.y as usize][module.x as usize];
+                let matched = module.pixel.dont_care() || dark == module.target_color;
+                let size = if matched { core.div_ceil(2) } else { core };
+                code.draw_core(module.x, module.y, dark, size, detail, brightness_threshold);
+            }
+        }
+
+        let report = if debug {
+            timer.next("analysis");
+            log::info!("analysing code...");
+            Some(Report::new(block_reports, &modules, &patterns, module_size))
+        } else {
+            None
+        };
+
+        stats.phases = timer.finish();
+
+        // the leftover pixels when the module size was chosen from the width
+        let mut image = code.image();
+        if let Some(width) = options.width.filter(|width| *width > image.width()) {
+            let mut padded = ImageBuffer::from_pixel(width, width, Rgba([255, 255, 255, 255]));
+            let offset = (width - image.width()) as i64 / 2;
+            imageops::replace(&mut padded, &image, offset, offset);
+            image = padded;
+        }
+
+        Ok((
+            Output {
+                image,
+                threshold: brightness_threshold,
+                report,
+                stats: Some(stats),
+                layout,
+                modules: Some(modules.clone()),
+            },
+            modules,
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use image::RgbImage;
+
+        // a target with some detail in it, so the order of the modules matters
+        fn target() -> DynamicImage {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+                let v =
+                    ((x * 7 + y * 13) % 256) as u8 ^ if (x / 8 + y / 8) % 2 == 0 { 0 } else { 255 };
+                image::Rgb([v, v, v])
+            }))
+        }
+
+        #[test]
+        fn same_seed_gives_the_same_code() {
+            for optimize in [
+                None,
+                Some(Optimize {
+                    iterations: 3,
+                    time_budget: None,
+                }),
+            ] {
+                let options = Options {
+                    module_size: 2,
+                    seed: Some(7),
+                    optimize,
+                    ..Default::default()
+                };
+                let build =
+                    || build(4, "https://example.com".to_string(), target(), &options).unwrap();
+                let (first, second) = (build(), build());
+                assert_eq!(first.image.as_raw(), second.image.as_raw());
+                assert_eq!(first.modules, second.modules);
+            }
+        }
+
+        #[test]
+        fn stats_describe_the_code() {
+            let options = Options {
+                seed: Some(1),
+                ..Default::default()
+            };
+            let output = build(5, "https://e.co".to_string(), target(), &options).unwrap();
+            let stats = output.stats.unwrap();
+            let side_len = output.modules.unwrap().len();
+
+            assert!(stats.match_rate > 0.5 && stats.match_rate <= 1.0);
+            assert!((0.0..=1.0).contains(&stats.weighted_match_rate));
+            assert!(stats.controllable_modules > 0 && stats.fixed_modules > 0);
+            assert!(stats.controllable_modules + stats.fixed_modules < side_len * side_len);
+            assert!(!stats.phases.is_empty());
+            assert!(stats.phases.iter().all(|phase| phase.millis >= 0.0));
+        }
+
+        #[test]
+        fn crops_outside_of_the_image_are_rejected() {
+            for (x, y, width, height) in [(5000, 5000, 10, 10), (60, 0, 10, 10), (0, 0, 0, 0)] {
+                let options = Options {
+                    placement: Placement {
+                        crop: Some(target::Crop {
+                            x,
+                            y,
+                            width,
+                            height,
+                        }),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                assert!(prepare_target(1, target(), &options).is_err());
+                assert!(preview(1, "https://e.co".to_string(), target(), &options).is_err());
+            }
+
+            let options = Options {
+                placement: Placement {
+                    crop: Some(target::Crop {
+                        x: 16,
+                        y: 16,
+                        width: 48,
+                        height: 48,
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert!(prepare_target(1, target(), &options).is_ok());
+        }
+
+        #[test]
+        fn jitter_keeps_small_weights_ahead_of_zero_ones() {
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut pixels = (0..200)
+                .map(|i| target::Pixel {
+                    priority: i % 2 * 2,
+                    brightness: 0,
+                    care: 254,
+                })
+                .collect::<Vec<_>>();
+            order_modules(&mut pixels, |pixel| *pixel, false, 0.9, &mut rng);
+            let first_zero = pixels.iter().position(|pixel| pixel.weight() == 0).unwrap();
+            assert!(pixels[first_zero..].iter().all(|pixel| pixel.weight() == 0));
+        }
+
+        #[test]
+        fn numbers_stay_in_range() {
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut split_groups = 0;
+
+            for version in [1, 6, 10, 13] {
+                let version = Version::new(version).unwrap();
+                let url = prepare_url("https://e.co".to_string(), version).unwrap();
+                let field = Rc::new(Field::new());
+                let generator_poly =
+                    gf::gen_poly(Rc::clone(&field), consts::ec_bytes_per_block(version));
+                let bits = encode_chars_to_bits(version, url);
+
+                let numeric = bits
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, bit)| bit.is_num())
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                let groups = numeric
+                    .chunks(10)
+                    .map(|group| group[0]..group[0] + group.len())
+                    .collect::<Vec<_>>();
+
+                for _ in 0..3 {
+                    let blocks =
+                        gen_blocks(bits.clone(), version, Rc::clone(&field), &generator_poly);
+
+                    // every bit of every block is asked for a random value, in a random order
+                    let mut data = Vec::with_capacity(bits.len());
+                    let mut ends = Vec::with_capacity(blocks.len());
+                    for block in blocks {
+                        let len = (block.num_data_bytes() + block.num_ec_bytes()) * 8;
+                        let mut requests = (0..len)
+                            .map(|index| (index, rng.gen_range(0..=1)))
+                            .collect::<Vec<_>>();
+                        requests.shuffle(&mut rng);
+
+                        let (block, _) = solve_block(block, requests);
+                        let num_data_bits = block.num_data_bytes() * 8;
+                        data.extend_from_slice(&block.ret()[..num_data_bits]);
+                        ends.push(data.len());
+                    }
+
+                    for (index, bit) in bits.iter().enumerate() {
+                        if !bit.can_edit() {
+                            assert_eq!(data[index], bit.val as u8, "the encoded text changed");
+                        }
+                    }
+                    for group in groups.iter() {
+                        let value = group
+                            .clone()
+                            .fold(0u16, |value, i| value << 1 | data[i] as u16);
+                        let max = match group.len() {
+                            10 => 999,
+                            7 => 99,
+                            _ => 9,
+                        };
+                        assert!(value <= max, "{value} doesnt fit in {} bits", group.len());
+                        split_groups += ends
+                            .iter()
+                            .any(|end| group.start < *end && *end < group.end)
+                            as usize;
+                    }
+                }
+            }
+
+            // groups that are split between two blocks are the hardest case, so they have to be covered
+            assert!(split_groups > 0);
+        }
     }
 }
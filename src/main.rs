@@ -1,5 +1,25 @@
-use clap::{Parser, Subcommand};
+use anyhow::Context;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use image::{DynamicImage, ImageFormat};
+use qart::decode;
+use qart::optimize::Optimize;
+use qart::priority::{self, Priority, PriorityMap, Region, Regions};
 use qart::qr;
+use qart::sim::{self, Profile};
+use qart::style::{ModuleStyle, PatternStyle};
+use qart::svg;
+use qart::target::{Crop, Fit, Layout, Levels, Mask, Placement, Threshold, Transparency};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod config;
+mod frames;
+mod serve;
 
 #[derive(Parser)]
 #[command(name = "qart")]
@@ -16,47 +36,570 @@ enum Commands {
         version: u8,
         /// URL that the QR code will point to. Should not contain URL fragments or query strings.
         url: String,
-        /// Relative path of the target image that the QR code will look like
+        /// Relative path of the target image that the QR code will look like, or - to read it from stdin
         image_path: String,
-        /// Path that the produced QR code will be saved to. Default is "code.png"
+        /// Path that the produced QR code will be saved to, or - to write it to stdout
         save_path: String,
-        /// The side length of each of the modules of the QR code in pixels. Default is 5
-        #[arg(long, default_value_t = 5)]
-        module_size: u32,
-        /// The brightness value at which brighter pixels will be white, and darker pixels will be black. Default is 128
-        #[arg(long, default_value_t = 128)]
-        threshold: u8,
-        /// Display the time taken to generate the QR code
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        size: SizeArgs,
+        #[command(flatten)]
+        image: ImageArgs,
+        #[command(flatten)]
+        priority: PriorityArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        optimize: OptimizeArgs,
+        /// Print how closely the code follows the image, how many modules could be controlled, and the time taken by
+        /// each phase of generating the code
+        #[arg(long)]
+        stats: bool,
+        /// Print the statistics from --stats as JSON, and nothing else
         #[arg(long)]
-        benchmark: bool,
-        /// Distribute uncontrollable pixels randomly instead of based off of contrast
+        json: bool,
+        /// Distribute uncontrollable pixels randomly instead of based off of priority
         #[arg(long)]
         random: bool,
-        /// create debug version of QR codes
+        /// Seed for the random parts of generation, the same seed always gives the same code
+        #[arg(long)]
+        seed: Option<u64>,
+        /// create debug version of QR codes, print a robustness report of the code, and save a heatmap of the
+        /// parts of the code that are hard to scan to report.png
         #[arg(long, default_value_t = false)]
         debug: bool,
     },
-    /// Generate a preview of a QR code that will quickly show what the image will look like as part of the QR code
+    /// Build a QR code for every frame of an animation, all pointing to the same URL
+    Animate {
+        /// Version number (size) of the QR codes: 1-40
+        version: u8,
+        /// URL that the QR codes will point to. Should not contain URL fragments or query strings.
+        url: String,
+        /// Relative path of an animated GIF or PNG, or of a directory of frames that are used in order of their names
+        image_path: String,
+        /// Path that the animation will be saved to: a GIF, an animated PNG, or a directory that every frame is saved
+        /// to as a PNG
+        save_path: String,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        size: SizeArgs,
+        #[command(flatten)]
+        image: ImageArgs,
+        #[command(flatten)]
+        priority: PriorityArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+        #[command(flatten)]
+        style: StyleArgs,
+        /// How strongly modules keep their color from one frame to the next, which cuts down on flicker. 0 builds
+        /// every frame on its own. Default is 0.5
+        #[arg(long, default_value_t = 0.5)]
+        stability: f32,
+        /// How long each frame is shown in milliseconds, when the frames don't say. Default is 100
+        #[arg(long, default_value_t = 100)]
+        delay: u64,
+        /// Distribute uncontrollable pixels randomly instead of based off of priority
+        #[arg(long)]
+        random: bool,
+        /// Seed for the random parts of generation, the same seed always gives the same codes
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Generate a preview at one pixel per module that quickly shows which modules will follow the image, with the
+    /// modules left to the rest of the code in gray
     Preview {
         /// Version number (size) of the QR code: 1-40
         version: u8,
-        /// Relative path of the target image that the QR code will look like
+        /// URL that the QR code will point to, which decides the modules that can't follow the image
+        url: String,
+        /// Relative path of the target image that the QR code will look like, or - to read it from stdin
         image_path: String,
-        /// Path that the produced QR code will be saved to. Default is "preview.png"
+        /// Path that the produced QR code will be saved to, or - to write it to stdout. Default is "preview.png".
+        /// It can't be an SVG, since an SVG only has dark and light modules and can't show the gray ones
         #[arg(long, default_value = "preview.png")]
         save_path: String,
-        /// The brightness value at which brighter pixels will be white, and darker pixels will be black. Default is 128
-        #[arg(long, default_value_t = 128)]
-        threshold: u8,
-        /// Distribute uncontrollable pixels randomly instead of based off of contrast
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        image: ImageArgs,
+        #[command(flatten)]
+        priority: PriorityArgs,
+        #[command(flatten)]
+        placement: PlacementArgs,
+        /// Distribute uncontrollable pixels randomly instead of based off of priority
+        #[arg(long)]
+        random: bool,
+        /// Seed for the random parts of generation, the same seed always gives the same code
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Read an existing QR code and build one that points to a different URL and looks as much like it as possible,
+    /// with the same version and mask pattern
+    Rebuild {
+        /// Relative path of the QR code image to rebuild, or - to read it from stdin
+        image_path: String,
+        /// URL that the new QR code will point to. Should not contain URL fragments or query strings.
+        url: String,
+        /// Path that the new QR code will be saved to, or - to write it to stdout
+        save_path: String,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// The side length of each of the modules of the new QR code in pixels. Default is the size of the modules in
+        /// the old code
+        #[arg(long)]
+        module_size: Option<u32>,
+        /// The width of the light margin around the code in modules. Scanners expect at least 4. Default is 10
+        #[arg(long, default_value_t = 10)]
+        quiet_zone: u32,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        optimize: OptimizeArgs,
+        /// Print how closely the new code follows the old one, how many modules could be controlled, and the time
+        /// taken by each phase of generating the code
+        #[arg(long)]
+        stats: bool,
+        /// Distribute uncontrollable pixels randomly instead of based off of priority
         #[arg(long)]
         random: bool,
+        /// Seed for the random parts of generation, the same seed always gives the same code
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Check whether a QR code still scans after being blurred, compressed, made noisy, warped and scaled down
+    Verify {
+        /// Relative path of the QR code image to check, or - to read it from stdin
+        image_path: String,
+        /// How strongly the image is degraded: mild, phone or harsh. Default is phone
+        #[arg(long, default_value = "phone")]
+        profile: Profile,
+        /// The number of degraded versions to decode for each kind of degradation. Default is 10
+        #[arg(long, default_value_t = 10)]
+        trials: usize,
+        /// Seed for the degradations, the same seed always gives the same results
+        #[arg(long)]
+        seed: Option<u64>,
     },
+    /// Run an HTTP server that builds codes on demand. POST a JSON object to /build with the target image file as
+    /// base64 in "image", the "payload" and "version", and optionally "threshold", "format" (png or svg), "seed",
+    /// "module_size", "width" and "quiet_zone"
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+        /// The most codes that are built at the same time. Default is the number of cpus
+        #[arg(long)]
+        jobs: Option<usize>,
+        /// The most requests that are handled at the same time, the rest wait until one of them is done. Default is 4
+        /// times --jobs
+        #[arg(long)]
+        connections: Option<usize>,
+        /// The number of processed images to keep, so codes for an image that was already used skip processing it.
+        /// Default is 32
+        #[arg(long, default_value_t = 32)]
+        cache_size: usize,
+    },
+}
+
+#[derive(Args)]
+struct ImageArgs {
+    /// The brightness value at which brighter pixels will be white, and darker pixels will be black,
+    /// or "auto" to pick one from the image's histogram. Default is 128
+    #[arg(long, default_value = "128")]
+    threshold: Threshold,
+    /// Stretch the brightness of the image to cover the full range before thresholding
+    #[arg(long)]
+    stretch: bool,
+    /// Gamma correction applied before thresholding, values below 1 brighten the image. Default is 1
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+    /// How transparent parts of the image are handled: a hex color to use as the background,
+    /// or "dont-care" to leave them for the rest of the code. Default is #ffffff
+    #[arg(long, default_value = "#ffffff")]
+    transparency: Transparency,
+}
+
+impl ImageArgs {
+    fn levels(&self) -> Levels {
+        Levels {
+            stretch: self.stretch,
+            gamma: self.gamma,
+        }
+    }
+}
+
+#[derive(Args)]
+struct PriorityArgs {
+    /// How modules are prioritized: variance, edges, saliency, center, regions, or the path of a grayscale
+    /// image where brighter pixels are more important. Default is variance
+    #[arg(long, default_value = "variance")]
+    priority: String,
+    /// A region to prioritize when using --priority regions, given as x,y,width,height in fractions of the image size,
+    /// and an optional weight. Can be repeated
+    #[arg(long = "region", value_parser = parse_region)]
+    regions: Vec<Region>,
+    /// Path of a grayscale mask the same shape as the image. Modules under white areas must match the image,
+    /// modules under black areas are left for the rest of the code, and gray areas scale the priority in between
+    #[arg(long)]
+    mask: Option<String>,
+}
+
+impl PriorityArgs {
+    fn priority(&self) -> anyhow::Result<Arc<dyn Priority>> {
+        Ok(match self.priority.as_str() {
+            "variance" => Arc::new(priority::Variance),
+            "edges" => Arc::new(priority::Edges),
+            "saliency" => Arc::new(priority::Saliency),
+            "center" => Arc::new(priority::Center),
+            "regions" => Arc::new(Regions(self.regions.clone())),
+            path => {
+                let map = image::open(path).context("Could not open priority map")?;
+                Arc::new(PriorityMap(map.to_luma8()))
+            }
+        })
+    }
+
+    fn mask(&self) -> anyhow::Result<Option<Mask>> {
+        self.mask
+            .as_deref()
+            .map(|path| {
+                let mask = image::open(path).context("Could not open mask image")?;
+                Ok(Mask(mask.to_luma8()))
+            })
+            .transpose()
+    }
+}
+
+#[derive(Args)]
+struct PlacementArgs {
+    /// How the image is fitted into the code: stretch, contain (pad the leftover space) or cover (crop the sides).
+    /// Default is stretch
+    #[arg(long, value_enum, default_value_t = FitArg::Stretch)]
+    fit: FitArg,
+    /// The point that --fit cover crops around, as x,y in fractions of the image size. Default is 0.5,0.5
+    #[arg(long, value_parser = parse_pair::<f32>, default_value = "0.5,0.5")]
+    focus: (f32, f32),
+    /// Only use this part of the image, given as x,y,width,height in pixels
+    #[arg(long, value_parser = parse_crop)]
+    crop: Option<Crop>,
+    /// Side length of the image as a fraction of the side length of the code. Default is 1
+    #[arg(long, default_value_t = 1.0)]
+    scale: f32,
+    /// Move the image away from the center of the code, as x,y in modules. Default is 0,0
+    #[arg(long, value_parser = parse_pair::<i32>, default_value = "0,0", allow_hyphen_values = true)]
+    offset: (i32, i32),
+    /// Choose the scale and offset automatically so the important parts of the image avoid the finder,
+    /// timing and alignment patterns
+    #[arg(long)]
+    avoid_patterns: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FitArg {
+    Stretch,
+    Contain,
+    Cover,
+}
+
+impl PlacementArgs {
+    fn placement(&self) -> Placement {
+        Placement {
+            crop: self.crop,
+            fit: match self.fit {
+                FitArg::Stretch => Fit::Stretch,
+                FitArg::Contain => Fit::Contain,
+                FitArg::Cover => Fit::Cover {
+                    focus_x: self.focus.0,
+                    focus_y: self.focus.1,
+                },
+            },
+            scale: self.scale,
+            offset_x: self.offset.0,
+            offset_y: self.offset.1,
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        if self.avoid_patterns {
+            Layout::AvoidPatterns
+        } else {
+            Layout::Manual
+        }
+    }
+}
+
+#[derive(Args)]
+struct StyleArgs {
+    /// Draw alignment and timing patterns as dots in the center of their modules,
+    /// with the rest of the module following the image
+    #[arg(long)]
+    dot_patterns: bool,
+    /// The smallest width of the dots drawn by --dot-patterns in pixels. Default is 1
+    #[arg(long, default_value_t = 1)]
+    min_core: u32,
+    /// Also draw finder patterns as dots when using --dot-patterns. Makes the code harder to scan
+    #[arg(long)]
+    dot_finders: bool,
+    /// Draw the data modules as a square core this wide, as a fraction of the module, with the rest of the module
+    /// showing the image in gray. Modules that match the image get a core half as wide. Try 0.4
+    #[arg(long)]
+    module_core: Option<f32>,
+}
+
+impl StyleArgs {
+    fn patterns(&self) -> PatternStyle {
+        if self.dot_patterns {
+            PatternStyle::Dots {
+                min_core: self.min_core,
+                finders: self.dot_finders,
+            }
+        } else {
+            PatternStyle::Solid
+        }
+    }
+
+    fn modules(&self) -> ModuleStyle {
+        match self.module_core {
+            Some(core) => ModuleStyle::Cores { core },
+            None => ModuleStyle::Solid,
+        }
+    }
+}
+
+#[derive(Args)]
+struct OptimizeArgs {
+    /// Build several candidate codes with different thresholds, mask patterns and module orders,
+    /// and keep the one that looks the most like the image
+    #[arg(long)]
+    optimize: bool,
+    /// The most candidates built by --optimize. Default is 20
+    #[arg(long, default_value_t = 20)]
+    iterations: usize,
+    /// Stop building candidates after this many seconds and keep the best one so far
+    #[arg(long)]
+    time_budget: Option<f32>,
+}
+
+impl OptimizeArgs {
+    fn optimize(&self) -> Option<Optimize> {
+        self.optimize.then(|| Optimize {
+            iterations: self.iterations,
+            time_budget: self.time_budget.map(Duration::from_secs_f32),
+        })
+    }
+}
+
+#[derive(Args)]
+struct SizeArgs {
+    /// The side length of each of the modules of the QR code in pixels. Default is 5
+    #[arg(long, default_value_t = 5)]
+    module_size: u32,
+    /// The side length of the whole image in pixels, instead of --module-size. The modules are made as big as
+    /// fits, and the pixels left over are split evenly around the code
+    #[arg(long, conflicts_with = "module_size")]
+    width: Option<u32>,
+    /// The width of the light margin around the code in modules. Scanners expect at least 4. Default is 10
+    #[arg(long, default_value_t = 10)]
+    quiet_zone: u32,
+}
+
+#[derive(Args)]
+struct ConfigArgs {
+    /// Path of a TOML file with presets of options, as tables under [presets] that set options by their long names.
+    /// Options given on the command line override the preset
+    #[arg(long)]
+    config: Option<String>,
+    /// The preset from --config to use. Default is "default"
+    #[arg(long, requires = "config")]
+    preset: Option<String>,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// The format of the produced QR code: png, jpeg, webp or svg. Default is the extension of the save path, or png
+    /// when writing to stdout
+    #[arg(long)]
+    format: Option<FormatArg>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FormatArg {
+    Png,
+    Jpeg,
+    Webp,
+    Svg,
+}
+
+impl OutputArgs {
+    fn format(&self, save_path: &str) -> anyhow::Result<FormatArg> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        if save_path == "-" {
+            return Ok(FormatArg::Png);
+        }
+        let extension = Path::new(save_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("png") => Ok(FormatArg::Png),
+            Some("jpg" | "jpeg") => Ok(FormatArg::Jpeg),
+            Some("webp") => Ok(FormatArg::Webp),
+            Some("svg") => Ok(FormatArg::Svg),
+            _ => Err(anyhow::anyhow!(
+                "Could not tell the format from \"{save_path}\", choose one with --format"
+            )),
+        }
+    }
+
+    // encodes the code and writes it to the save path, or to stdout if the path is -. quiet_zone is the margin of svgs
+    // in modules
+    fn save(&self, code: &qr::Output, save_path: &str, quiet_zone: u32) -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        let image_format = match self.format(save_path)? {
+            FormatArg::Svg => {
+                let modules = code
+                    .modules
+                    .as_ref()
+                    .context("SVG is only available for built codes")?;
+                bytes = svg::svg(modules, quiet_zone).into_bytes();
+                None
+            }
+            FormatArg::Png => Some(ImageFormat::Png),
+            FormatArg::Webp => Some(ImageFormat::WebP),
+            FormatArg::Jpeg => Some(ImageFormat::Jpeg),
+        };
+        if let Some(image_format) = image_format {
+            // jpeg has no alpha channel
+            let mut image = DynamicImage::ImageRgba8(code.image.clone());
+            if image_format == ImageFormat::Jpeg {
+                image = DynamicImage::ImageRgb8(image.to_rgb8());
+            }
+            image
+                .write_to(&mut Cursor::new(&mut bytes), image_format)
+                .context("Could not encode image")?;
+        }
+
+        if save_path == "-" {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&bytes).and_then(|_| stdout.flush())
+        } else {
+            fs::write(save_path, bytes)
+        }
+        .context("Could not save image")
+    }
+}
+
+// opens an image from a path, or reads it from stdin if the path is -
+fn open_image(path: &str) -> anyhow::Result<DynamicImage> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Could not read stdin")?;
+        return Ok(image::load_from_memory(&bytes)?);
+    }
+    Ok(image::open(path)?)
+}
+
+// a comma separated list of numbers
+fn parse_numbers<T: std::str::FromStr>(s: &str) -> anyhow::Result<Vec<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(s.split(',')
+        .map(|v| v.trim().parse::<T>())
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+fn parse_pair<T: std::str::FromStr + Copy>(s: &str) -> anyhow::Result<(T, T)>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match parse_numbers::<T>(s)?[..] {
+        [x, y] => Ok((x, y)),
+        _ => Err(anyhow::anyhow!("expected x,y")),
+    }
+}
+
+fn parse_crop(s: &str) -> anyhow::Result<Crop> {
+    match parse_numbers::<u32>(s)?[..] {
+        [_, _, 0, _] | [_, _, _, 0] => {
+            Err(anyhow::anyhow!("crop width and height must be at least 1"))
+        }
+        [x, y, width, height] => Ok(Crop {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(anyhow::anyhow!("crop must be x,y,width,height")),
+    }
+}
+
+fn parse_region(s: &str) -> anyhow::Result<Region> {
+    let values = parse_numbers::<f32>(s)?;
+
+    match values[..] {
+        [x, y, width, height] => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+            weight: 1,
+        }),
+        [x, y, width, height, weight] => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+            weight: weight as u32,
+        }),
+        _ => Err(anyhow::anyhow!(
+            "region must be x,y,width,height or x,y,width,height,weight"
+        )),
+    }
+}
+
+// options shared by the build and preview subcommands
+fn options(
+    image: &ImageArgs,
+    priority: &PriorityArgs,
+    placement: &PlacementArgs,
+    random: bool,
+    seed: Option<u64>,
+) -> anyhow::Result<qr::Options> {
+    Ok(qr::Options {
+        threshold: image.threshold,
+        levels: image.levels(),
+        priority: priority.priority()?,
+        mask: priority.mask()?,
+        transparency: image.transparency,
+        placement: placement.placement(),
+        layout: placement.layout(),
+        random,
+        seed,
+        ..Default::default()
+    })
 }
 
 fn main() {
     env_logger::init();
-    let cli = Cli::parse();
+    let cli = match config::args(Cli::command()) {
+        Ok(args) => Cli::parse_from(args),
+        Err(e) => return log::error!("{:#}", e),
+    };
 
     match cli.command {
         Commands::Build {
@@ -64,20 +607,136 @@ fn main() {
             url,
             image_path,
             save_path,
-            module_size,
-            threshold,
-            benchmark,
+            output,
+            config: _,
+            size,
+            image,
+            priority,
+            placement,
+            style,
+            optimize,
+            stats,
+            json,
             random,
+            seed,
             debug,
         } => {
-            let start = std::time::Instant::now();
-            match qr::build(version, url, module_size, image_path, threshold, random, debug) {
-                Ok(img) => {
-                    if let Err(e) = img.save(save_path) {
-                        log::error!("Could not save image: {:#}", e);
+            let options = match options(&image, &priority, &placement, random, seed) {
+                Ok(options) => qr::Options {
+                    module_size: size.module_size,
+                    width: size.width,
+                    quiet_zone: size.quiet_zone,
+                    patterns: style.patterns(),
+                    modules: style.modules(),
+                    debug,
+                    optimize: optimize.optimize(),
+                    ..options
+                },
+                Err(e) => return log::error!("{:#}", e),
+            };
+            let target = match open_image(&image_path) {
+                Ok(target) => target,
+                Err(e) => return log::error!("Could not open target image: {:#}", e),
+            };
+            // when the code goes to stdout, everything else that would be printed goes to stderr
+            let print = |text: &dyn std::fmt::Display| {
+                if save_path == "-" {
+                    eprintln!("{text}");
+                } else {
+                    println!("{text}");
+                }
+            };
+            match qr::build(version, url, target, &options) {
+                Ok(code) => {
+                    if let Err(e) = output.save(&code, &save_path, options.quiet_zone) {
+                        log::error!("{:#}", e);
                     };
-                    if benchmark {
-                        println!("Time Elapsed: {:?}", start.elapsed());
+                    if let Some(layout) = code.layout {
+                        if let Err(e) = layout.save("debug.png") {
+                            log::error!("Could not save debug code: {:#}", e);
+                        }
+                    }
+                    if let Some(report) = code.report {
+                        if !json {
+                            print(&report);
+                        }
+                        if let Err(e) = report.heatmap.save("report.png") {
+                            log::error!("Could not save report heatmap: {:#}", e);
+                        }
+                    }
+                    if let Some(code_stats) = code.stats {
+                        if json {
+                            match serde_json::to_string_pretty(&code_stats) {
+                                Ok(json) => print(&json),
+                                Err(e) => log::error!("Could not write statistics: {:#}", e),
+                            }
+                        } else if stats {
+                            print(&code_stats);
+                        }
+                    }
+                }
+                Err(e) => log::error!("Could not create QR Code: {}", e),
+            }
+        }
+        Commands::Animate {
+            version,
+            url,
+            image_path,
+            save_path,
+            config: _,
+            size,
+            image,
+            priority,
+            placement,
+            style,
+            stability,
+            delay,
+            random,
+            seed,
+        } => {
+            let options = match options(&image, &priority, &placement, random, seed) {
+                Ok(options) => qr::Options {
+                    module_size: size.module_size,
+                    width: size.width,
+                    quiet_zone: size.quiet_zone,
+                    patterns: style.patterns(),
+                    modules: style.modules(),
+                    ..options
+                },
+                Err(e) => return log::error!("{:#}", e),
+            };
+            let (targets, delays): (Vec<_>, Vec<_>) =
+                match frames::open(&image_path, Duration::from_millis(delay)) {
+                    Ok(frames) => frames.into_iter().unzip(),
+                    Err(e) => return log::error!("Could not open target animation: {:#}", e),
+                };
+            match qr::animate(version, url, targets, &options, stability) {
+                Ok(codes) => {
+                    // the share of modules that flip from one frame to the next
+                    let modules = codes
+                        .iter()
+                        .filter_map(|code| code.modules.as_ref())
+                        .collect::<Vec<_>>();
+                    let changed = modules
+                        .windows(2)
+                        .map(|pair| {
+                            let flipped = pair[0].iter().flatten().zip(pair[1].iter().flatten());
+                            flipped.filter(|(a, b)| a != b).count()
+                        })
+                        .sum::<usize>();
+                    let compared = modules.len().saturating_sub(1) * modules[0].len().pow(2);
+                    log::info!(
+                        "{:.1}% of modules changed between frames",
+                        100.0 * changed as f32 / compared.max(1) as f32
+                    );
+
+                    let frames = codes
+                        .into_iter()
+                        .map(|code| code.image)
+                        .zip(delays)
+                        .collect();
+                    if let Err(e) = frames::save(frames, &save_path) {
+                        log::error!("Could not save animation: {:#}", e);
                     }
                 }
                 Err(e) => log::error!("Could not create QR Code: {}", e),
@@ -85,21 +744,160 @@ fn main() {
         }
         Commands::Preview {
             version,
+            url,
             image_path,
             save_path,
-            threshold,
+            output,
+            config: _,
+            image,
+            priority,
+            placement,
             random,
+            seed,
         } => {
-            let code = qr::preview(version, image_path, threshold, random);
+            if let Ok(FormatArg::Svg) = output.format(&save_path) {
+                return log::error!(
+                    "A preview can't be saved as an SVG, since an SVG only has dark and light modules and can't show \
+                     the gray ones. Save it as a PNG, JPEG or WebP instead"
+                );
+            }
+            let options = match options(&image, &priority, &placement, random, seed) {
+                Ok(options) => options,
+                Err(e) => return log::error!("{:#}", e),
+            };
+            let target = match open_image(&image_path) {
+                Ok(target) => target,
+                Err(e) => return log::error!("Could not open target image: {:#}", e),
+            };
+            let code = qr::preview(version, url, target, &options);
             match code {
-                Ok(img) => {
-                    if let Err(e) = img.save(save_path) {
-                        log::error!("Could not save image: {:#}", e);
+                Ok(code) => {
+                    if let Err(e) = output.save(&code, &save_path, options.quiet_zone) {
+                        log::error!("{:#}", e);
                     };
                 }
                 Err(e) => log::error!("{}", e),
             }
         }
+        Commands::Rebuild {
+            image_path,
+            url,
+            save_path,
+            output,
+            config: _,
+            module_size,
+            quiet_zone,
+            style,
+            optimize,
+            stats,
+            random,
+            seed,
+        } => {
+            let code = match open_image(&image_path) {
+                Ok(code) => code,
+                Err(e) => return log::error!("Could not open code: {:#}", e),
+            };
+            let decoded = match decode::decode(&code.to_luma8()) {
+                Ok(decoded) => decoded,
+                Err(e) => return log::error!("Could not read the code: {:#}", e),
+            };
+            log::info!(
+                "read a version {} code with mask pattern {} that points to {}",
+                decoded.version,
+                decoded.mask,
+                decoded.text
+            );
+
+            let options = qr::Options {
+                module_size: module_size.unwrap_or((decoded.module_size.round() as u32).max(1)),
+                quiet_zone,
+                patterns: style.patterns(),
+                modules: style.modules(),
+                optimize: optimize.optimize(),
+                random,
+                seed,
+                ..Default::default()
+            };
+            match qr::rebuild(url, &decoded, &options) {
+                Ok(code) => {
+                    if let Err(e) = output.save(&code, &save_path, options.quiet_zone) {
+                        log::error!("{:#}", e);
+                    };
+                    if let Some(code_stats) = code.stats.filter(|_| stats) {
+                        if save_path == "-" {
+                            eprintln!("{code_stats}");
+                        } else {
+                            println!("{code_stats}");
+                        }
+                    }
+                }
+                Err(e) => log::error!("Could not create QR Code: {}", e),
+            }
+        }
+        Commands::Verify {
+            image_path,
+            profile,
+            trials,
+            seed,
+        } => {
+            let image = match open_image(&image_path) {
+                Ok(image) => image,
+                Err(e) => return log::error!("Could not open image: {:#}", e),
+            };
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            match sim::verify(&image, profile, trials, &mut rng) {
+                Ok(verification) => println!("{verification}"),
+                Err(e) => log::error!("{:#}", e),
+            }
+        }
+        Commands::Serve {
+            address,
+            jobs,
+            connections,
+            cache_size,
+        } => {
+            let jobs = jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+            });
+            let connections = connections.unwrap_or(4 * jobs);
+            if let Err(e) = serve::serve(&address, jobs, connections, cache_size) {
+                log::error!("{:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_come_from_the_save_path() {
+        let output = OutputArgs { format: None };
+        assert!(matches!(output.format("-"), Ok(FormatArg::Png)));
+        assert!(matches!(output.format("code.PNG"), Ok(FormatArg::Png)));
+        assert!(matches!(output.format("code.jpg"), Ok(FormatArg::Jpeg)));
+        assert!(matches!(output.format("code.webp"), Ok(FormatArg::Webp)));
+        assert!(matches!(output.format("code.svg"), Ok(FormatArg::Svg)));
+        assert!(output.format("code.bmp").is_err());
+        assert!(output.format("code").is_err());
+
+        let output = OutputArgs {
+            format: Some(FormatArg::Svg),
+        };
+        assert!(matches!(output.format("-"), Ok(FormatArg::Svg)));
+        assert!(matches!(output.format("code.png"), Ok(FormatArg::Svg)));
+    }
+
+    #[test]
+    fn empty_crops_are_rejected() {
+        assert!(parse_crop("0,0,0,0").is_err());
+        assert!(parse_crop("5,5,10,0").is_err());
+        assert!(parse_crop("5,5,0,10").is_err());
+        assert!(parse_crop("5,5,10,10").is_ok());
     }
 }
 
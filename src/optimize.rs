@@ -0,0 +1,195 @@
+// builds several candidate codes with slightly different settings and keeps the one that looks the most like the target
+
+use crate::qr::Output;
+use crate::target::Pixel;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+use web_time::Instant;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Optimize {
+    // the most candidates to build, including the one with the unchanged settings
+    pub iterations: usize,
+    // stop building candidates once this much time has passed, the best one so far is kept
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for Optimize {
+    fn default() -> Self {
+        Optimize {
+            iterations: 20,
+            time_budget: None,
+        }
+    }
+}
+
+// the settings that are changed between candidates
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Variation {
+    pub threshold: u8,
+    // how much the priority of each module is randomly scaled by, which changes the order they are matched in
+    pub jitter: f32,
+    // which of the 8 qr mask patterns the data is xored with
+    pub mask_pattern: u8,
+}
+
+impl Variation {
+    // a random variation around the given one
+    fn random(base: &Variation, rng: &mut impl Rng) -> Self {
+        Variation {
+            threshold: (base.threshold as i32 + rng.gen_range(-24..=24)).clamp(1, 255) as u8,
+            jitter: rng.gen_range(0.0..0.5),
+            mask_pattern: rng.gen_range(0..8),
+        }
+    }
+}
+
+// build returns the finished code and whether each module is dark
+pub(crate) fn optimize(
+    settings: Optimize,
+    base: Variation,
+    target: &[Vec<Pixel>],
+    rng: &mut StdRng,
+    mut build: impl FnMut(&Variation, &mut StdRng) -> anyhow::Result<(Output, Vec<Vec<bool>>)>,
+) -> anyhow::Result<Output> {
+    let start = Instant::now();
+    let mut best: Option<(f32, Output)> = None;
+
+    for i in 0..settings.iterations.max(1) {
+        if settings
+            .time_budget
+            .is_some_and(|budget| start.elapsed() > budget)
+            && best.is_some()
+        {
+            log::info!("time budget used up after {i} candidates");
+            break;
+        }
+
+        // the first candidate is always the unchanged settings, so optimizing never does worse than not optimizing
+        let variation = if i == 0 {
+            base
+        } else {
+            Variation::random(&base, rng)
+        };
+        // each candidate gets its own rng so that they dont depend on how much randomness the others used
+        let mut candidate_rng = StdRng::seed_from_u64(rng.gen());
+
+        let (output, modules) = build(&variation, &mut candidate_rng)?;
+        let score = similarity(&modules, target);
+        log::info!(
+            "candidate {i}: threshold {}, jitter {:.2}, mask {}, similarity {score:.4}",
+            variation.threshold,
+            variation.jitter,
+            variation.mask_pattern,
+        );
+
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, _)| score > *best_score)
+        {
+            best = Some((score, output));
+        }
+    }
+
+    let (score, output) = best.expect("at least one candidate is built");
+    log::info!("best similarity: {score:.4}");
+    Ok(output)
+}
+
+// structural similarity (https://en.wikipedia.org/wiki/Structural_similarity) between the code and the target at the
+// resolution of the modules. both are blurred first, because from a distance the eye averages neighbouring modules.
+// modules are weighted by how much they matter according to the mask
+fn similarity(modules: &[Vec<bool>], target: &[Vec<Pixel>]) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let code = modules
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|dark| if *dark { 0.0 } else { 1.0 })
+                .collect()
+        })
+        .collect::<Vec<Vec<f32>>>();
+    let image = target
+        .iter()
+        .map(|row| row.iter().map(|p| p.brightness as f32 / 255.0).collect())
+        .collect::<Vec<Vec<f32>>>();
+
+    let code = gaussian(&code, 1.0);
+    let image = gaussian(&image, 1.0);
+
+    let product = |a: &[Vec<f32>], b: &[Vec<f32>]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.iter().zip(b).map(|(a, b)| a * b).collect())
+            .collect::<Vec<Vec<f32>>>()
+    };
+
+    let mean_code = gaussian(&code, 1.5);
+    let mean_image = gaussian(&image, 1.5);
+    let code_sq = gaussian(&product(&code, &code), 1.5);
+    let image_sq = gaussian(&product(&image, &image), 1.5);
+    let cross = gaussian(&product(&code, &image), 1.5);
+
+    let (mut total, mut weights) = (0.0, 0.0);
+    for y in 0..code.len() {
+        for x in 0..code.len() {
+            let weight = target[y][x].care as f32 / 255.0;
+            if weight == 0.0 {
+                continue;
+            }
+            let (mc, mi) = (mean_code[y][x], mean_image[y][x]);
+            let var_code = code_sq[y][x] - mc * mc;
+            let var_image = image_sq[y][x] - mi * mi;
+            let covariance = cross[y][x] - mc * mi;
+
+            let ssim = ((2.0 * mc * mi + C1) * (2.0 * covariance + C2))
+                / ((mc * mc + mi * mi + C1) * (var_code + var_image + C2));
+            total += ssim * weight;
+            weights += weight;
+        }
+    }
+
+    if weights == 0.0 {
+        0.0
+    } else {
+        total / weights
+    }
+}
+
+// separable gaussian blur of a square grid, edges are clamped
+fn gaussian(grid: &[Vec<f32>], sigma: f32) -> Vec<Vec<f32>> {
+    let side_len = grid.len() as i32;
+    let reach = (sigma * 3.0).ceil() as i32;
+    let kernel = (-reach..=reach)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let sum: f32 = kernel.iter().sum();
+
+    let pass = |grid: &[Vec<f32>], horizontal: bool| {
+        (0..side_len)
+            .map(|y| {
+                (0..side_len)
+                    .map(|x| {
+                        (-reach..=reach)
+                            .zip(&kernel)
+                            .map(|(i, k)| {
+                                let (sx, sy) = if horizontal {
+                                    ((x + i).clamp(0, side_len - 1), y)
+                                } else {
+                                    (x, (y + i).clamp(0, side_len - 1))
+                                };
+                                grid[sy as usize][sx as usize] * k
+                            })
+                            .sum::<f32>()
+                            / sum
+                    })
+                    .collect()
+            })
+            .collect::<Vec<Vec<f32>>>()
+    };
+
+    pass(&pass(grid, true), false)
+}
@@ -0,0 +1,267 @@
+// importance maps that decide which modules of the code get to match the target image first
+
+use image::{imageops::FilterType, GrayImage};
+use std::fmt::Debug;
+
+pub trait Priority: Debug + Send + Sync {
+    // takes the brightness of the whole target, uncropped, at about one pixel per module it is drawn over, and returns
+    // a priority for each pixel. the priorities are then cropped and placed on the code like the target. modules with
+    // a higher priority are matched to the target before modules with a lower one
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>>;
+}
+
+// the variance of the brightness in a 10x10 window around each module
+#[derive(Debug, Default)]
+pub struct Variance;
+
+impl Priority for Variance {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        map(brightness, |x, y| variance(x, y, brightness))
+    }
+}
+
+fn variance(target_x: usize, target_y: usize, brightness: &[Vec<u8>]) -> u32 {
+    let range = 5;
+
+    let mut n = 0;
+    let mut sum: usize = 0;
+    let mut sum_sequence: usize = 0;
+
+    for offset_y in 0..(range * 2) {
+        for offset_x in 0..(range * 2) {
+            let pixel_y = (target_y as i32 - range) + offset_y;
+            let pixel_x = (target_x as i32 - range) + offset_x;
+            if pixel_y >= 0
+                && (pixel_y as usize) < brightness.len()
+                && pixel_x >= 0
+                && (pixel_x as usize) < brightness[0].len()
+            {
+                let v = brightness[pixel_y as usize][pixel_x as usize];
+
+                sum += v as usize;
+                sum_sequence += v as usize * v as usize;
+
+                n += 1;
+            }
+        }
+    }
+
+    let avg = sum / n;
+    let contrast = sum_sequence / n - avg * avg;
+
+    contrast as u32
+}
+
+// the magnitude of the sobel gradient at each module, so outlines are matched first
+// https://en.wikipedia.org/wiki/Sobel_operator
+#[derive(Debug, Default)]
+pub struct Edges;
+
+impl Priority for Edges {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        map(brightness, |x, y| {
+            let p = |dx: i32, dy: i32| clamped(brightness, x as i32 + dx, y as i32 + dy) as i32;
+
+            let gx = (p(1, -1) + 2 * p(1, 0) + p(1, 1)) - (p(-1, -1) + 2 * p(-1, 0) + p(-1, 1));
+            let gy = (p(-1, 1) + 2 * p(0, 1) + p(1, 1)) - (p(-1, -1) + 2 * p(0, -1) + p(1, -1));
+
+            ((gx * gx + gy * gy) as f64).sqrt() as u32
+        })
+    }
+}
+
+// frequency tuned saliency: how far a slightly blurred module is from the average brightness of the whole target
+// https://infoscience.epfl.ch/record/135217/files/1708.pdf
+#[derive(Debug, Default)]
+pub struct Saliency;
+
+impl Priority for Saliency {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        let pixels = brightness.iter().flatten().count().max(1);
+        let mean = brightness.iter().flatten().map(|v| *v as u32).sum::<u32>() / pixels as u32;
+
+        // 5x5 binomial blur
+        let kernel = [1, 4, 6, 4, 1];
+        map(brightness, |x, y| {
+            let mut sum = 0;
+            for (ky, wy) in kernel.iter().enumerate() {
+                for (kx, wx) in kernel.iter().enumerate() {
+                    let v = clamped(
+                        brightness,
+                        x as i32 + kx as i32 - 2,
+                        y as i32 + ky as i32 - 2,
+                    );
+                    sum += wy * wx * v as u32;
+                }
+            }
+            (sum / 256).abs_diff(mean)
+        })
+    }
+}
+
+// modules closer to the center of the target are matched first
+#[derive(Debug, Default)]
+pub struct Center;
+
+impl Priority for Center {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        let center_y = (brightness.len() as f64 - 1.0) / 2.0;
+        let center_x = (brightness.first().map_or(0, |row| row.len()) as f64 - 1.0) / 2.0;
+        let max_distance = (center_x * center_x + center_y * center_y).sqrt().max(1.0);
+
+        map(brightness, |x, y| {
+            let dx = x as f64 - center_x;
+            let dy = y as f64 - center_y;
+            ((1.0 - (dx * dx + dy * dy).sqrt() / max_distance) * 1000.0) as u32
+        })
+    }
+}
+
+// a rectangle in fractions of the target's width and height, eg. the bounding box of a face
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub weight: u32,
+}
+
+// only modules inside of the regions are prioritized, by the weight of the heaviest region they are in
+#[derive(Debug, Default)]
+pub struct Regions(pub Vec<Region>);
+
+impl Priority for Regions {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        let height = brightness.len() as f32;
+        let width = brightness.first().map_or(0, |row| row.len()) as f32;
+
+        map(brightness, |x, y| {
+            let fx = (x as f32 + 0.5) / width;
+            let fy = (y as f32 + 0.5) / height;
+            self.0
+                .iter()
+                .filter(|r| fx >= r.x && fx < r.x + r.width && fy >= r.y && fy < r.y + r.height)
+                .map(|r| r.weight)
+                .max()
+                .unwrap_or(0)
+        })
+    }
+}
+
+// an explicit grayscale image where brighter pixels are more important, scaled to the size of the code
+#[derive(Debug)]
+pub struct PriorityMap(pub GrayImage);
+
+impl Priority for PriorityMap {
+    fn priority(&self, brightness: &[Vec<u8>]) -> Vec<Vec<u32>> {
+        let height = brightness.len() as u32;
+        let width = brightness.first().map_or(0, |row| row.len()) as u32;
+        let scaled = image::imageops::resize(&self.0, width, height, FilterType::Gaussian);
+
+        map(brightness, |x, y| {
+            scaled.get_pixel(x as u32, y as u32).0[0] as u32
+        })
+    }
+}
+
+fn map(brightness: &[Vec<u8>], f: impl Fn(usize, usize) -> u32) -> Vec<Vec<u32>> {
+    brightness
+        .iter()
+        .enumerate()
+        .map(|(y, row)| (0..row.len()).map(|x| f(x, y)).collect())
+        .collect()
+}
+
+fn clamped(brightness: &[Vec<u8>], x: i32, y: i32) -> u8 {
+    let y = y.clamp(0, brightness.len() as i32 - 1) as usize;
+    let x = x.clamp(0, brightness[y].len() as i32 - 1) as usize;
+    brightness[y][x]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dark square in the middle of a light 20x20 image
+    fn square() -> Vec<Vec<u8>> {
+        (0..20)
+            .map(|y| {
+                (0..20)
+                    .map(|x| {
+                        if (5..15).contains(&x) && (5..15).contains(&y) {
+                            0
+                        } else {
+                            255
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edges_are_highest_on_outlines() {
+        let priority = Edges.priority(&square());
+        assert!(priority[10][5] > priority[10][10]);
+        assert!(priority[10][5] > priority[10][1]);
+        assert_eq!(priority[10][10], 0);
+    }
+
+    #[test]
+    fn flat_images_have_no_variance() {
+        let priority = Variance.priority(&vec![vec![90; 20]; 20]);
+        assert!(priority.iter().flatten().all(|p| *p == 0));
+        assert!(Variance.priority(&square())[10][5] > 0);
+    }
+
+    #[test]
+    fn center_is_highest_in_the_middle() {
+        let priority = Center.priority(&vec![vec![0; 21]; 21]);
+        assert_eq!(priority[10][10], 1000);
+        assert!(priority[10][10] > priority[5][10]);
+        assert!(priority[5][10] > priority[0][0]);
+    }
+
+    #[test]
+    fn saliency_picks_out_the_square() {
+        let priority = Saliency.priority(&square());
+        assert!(priority[10][10] > priority[1][1]);
+    }
+
+    #[test]
+    fn regions_use_the_heaviest_weight() {
+        let regions = Regions(vec![
+            Region {
+                x: 0.0,
+                y: 0.0,
+                width: 0.5,
+                height: 0.5,
+                weight: 3,
+            },
+            Region {
+                x: 0.25,
+                y: 0.25,
+                width: 0.5,
+                height: 0.5,
+                weight: 7,
+            },
+        ]);
+        let priority = regions.priority(&vec![vec![0; 20]; 20]);
+        assert_eq!(priority[1][1], 3);
+        assert_eq!(priority[7][7], 7);
+        assert_eq!(priority[12][12], 7);
+        assert_eq!(priority[18][18], 0);
+    }
+
+    #[test]
+    fn priority_maps_are_scaled_to_the_target() {
+        let map = PriorityMap(GrayImage::from_fn(4, 4, |x, _| {
+            image::Luma([if x < 2 { 0 } else { 255 }])
+        }));
+        let priority = map.priority(&vec![vec![0; 40]; 30]);
+        assert_eq!(priority.len(), 30);
+        assert_eq!(priority[0].len(), 40);
+        assert!(priority[15][2] < priority[15][37]);
+    }
+}
@@ -0,0 +1,170 @@
+// python bindings, built into a module with maturin. images can be passed in as PIL images, numpy arrays of uint8 with
+// 1, 3 or 4 channels, or the bytes of an encoded image file, and the code comes back as a PIL image, a numpy array, or
+// a (width, height, rgba bytes) tuple
+
+pub use crate::bindings::Options;
+use crate::qr;
+use crate::target;
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes};
+
+#[pymethods]
+impl Options {
+    // one argument per setting, since they are keyword arguments in python. the defaults are the same as
+    // Options::default
+    #[allow(clippy::too_many_arguments)]
+    #[new]
+    #[pyo3(signature = (*, module_size=5, width=None, quiet_zone=10, threshold=Some(128), seed=None, random=false, candidates=0, dot_patterns=false, module_core=None))]
+    fn py_new(
+        module_size: u32,
+        width: Option<u32>,
+        quiet_zone: u32,
+        threshold: Option<u8>,
+        seed: Option<u64>,
+        random: bool,
+        candidates: u32,
+        dot_patterns: bool,
+        module_core: Option<f32>,
+    ) -> Self {
+        Options {
+            module_size,
+            width,
+            quiet_zone,
+            threshold,
+            seed,
+            random,
+            candidates,
+            dot_patterns,
+            module_core,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+fn target(image: &Bound<'_, PyAny>) -> PyResult<DynamicImage> {
+    // the contents of an image file
+    if image.is_instance_of::<PyBytes>() || image.is_instance_of::<PyByteArray>() {
+        let bytes = image.extract::<Vec<u8>>()?;
+        return image::load_from_memory(&bytes)
+            .map_err(|e| PyValueError::new_err(format!("Could not read target image: {e}")));
+    }
+
+    // a numpy array
+    if image.hasattr("__array_interface__")? {
+        let shape = image.getattr("shape")?.extract::<Vec<usize>>()?;
+        let dtype = image.getattr("dtype")?.str()?.to_string();
+        if dtype != "uint8" {
+            return Err(PyTypeError::new_err(format!(
+                "Target array must be uint8, not {dtype}"
+            )));
+        }
+        let bytes = image.call_method0("tobytes")?.extract::<Vec<u8>>()?;
+        let (height, width) = match shape[..] {
+            [height, width] | [height, width, _] => (height as u32, width as u32),
+            _ => (0, 0),
+        };
+        let target = match shape[..] {
+            [_, _] | [_, _, 1] => {
+                GrayImage::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8)
+            }
+            [_, _, 3] => RgbImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+            [_, _, 4] => RgbaImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8),
+            _ => None,
+        };
+        return target.ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Target array must have a shape of (height, width) or (height, width, 1, 3 or 4), not {shape:?}"
+            ))
+        });
+    }
+
+    // a PIL image
+    if image.hasattr("convert")? && image.hasattr("size")? {
+        let rgba = image.call_method1("convert", ("RGBA",))?;
+        let (width, height) = rgba.getattr("size")?.extract::<(u32, u32)>()?;
+        let bytes = rgba.call_method0("tobytes")?.extract::<Vec<u8>>()?;
+        return target::from_rgba(bytes, width, height)
+            .ok_or_else(|| PyValueError::new_err("Target image does not match its size"));
+    }
+
+    Err(PyTypeError::new_err(
+        "Target must be a PIL image, a numpy array, or the bytes of an image file",
+    ))
+}
+
+fn output<'py>(py: Python<'py>, image: RgbaImage, format: &str) -> PyResult<Bound<'py, PyAny>> {
+    let (width, height) = image.dimensions();
+    match format {
+        "pil" => py.import("PIL.Image")?.call_method1(
+            "frombytes",
+            ("RGBA", (width, height), PyBytes::new(py, image.as_raw())),
+        ),
+        // a bytearray keeps the array writable
+        "numpy" => py
+            .import("numpy")?
+            .call_method1(
+                "frombuffer",
+                (PyByteArray::new(py, image.as_raw()), "uint8"),
+            )?
+            .call_method1("reshape", ((height, width, 4),)),
+        "rgba" => (width, height, PyBytes::new(py, image.as_raw()))
+            .into_pyobject(py)
+            .map(Bound::into_any),
+        _ => Err(PyValueError::new_err(format!(
+            "format must be \"pil\", \"numpy\" or \"rgba\", not \"{format}\""
+        ))),
+    }
+}
+
+// builds a functional qr code that looks like the image. the gil is released while the code is solved, so other
+// python threads can run, or build codes of their own
+#[pyfunction]
+#[pyo3(signature = (version, url, image, options=None, format="pil"))]
+fn build<'py>(
+    py: Python<'py>,
+    version: u8,
+    url: String,
+    image: &Bound<'py, PyAny>,
+    options: Option<Options>,
+    format: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let target = target(image)?;
+    let options = qr::Options::from(&options.unwrap_or_default());
+    let code = py
+        .detach(|| qr::build(version, url, target, &options))
+        .map_err(|e| PyValueError::new_err(format!("Could not create QR Code: {:#}", e)))?;
+    output(py, code.image, format)
+}
+
+// quickly shows which modules will follow the image at one pixel per module, with the modules left to the rest of the
+// code in gray
+#[pyfunction]
+#[pyo3(signature = (version, url, image, options=None, format="pil"))]
+fn preview<'py>(
+    py: Python<'py>,
+    version: u8,
+    url: String,
+    image: &Bound<'py, PyAny>,
+    options: Option<Options>,
+    format: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let target = target(image)?;
+    let options = qr::Options::from(&options.unwrap_or_default());
+    let code = py
+        .detach(|| qr::preview(version, url, target, &options))
+        .map_err(|e| PyValueError::new_err(format!("{:#}", e)))?;
+    output(py, code.image, format)
+}
+
+#[pymodule]
+fn qart(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Options>()?;
+    module.add_function(wrap_pyfunction!(build, module)?)?;
+    module.add_function(wrap_pyfunction!(preview, module)?)?;
+    Ok(())
+}
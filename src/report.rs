@@ -0,0 +1,223 @@
+// an analysis of how robust a finished code is, produced by build when debug is set
+
+use crate::layout::Pattern;
+use image::{Rgba, RgbaImage};
+use std::fmt;
+
+// how much of each block was decided by the target image
+#[derive(Clone, Debug)]
+pub struct BlockReport {
+    pub data_codewords: usize,
+    pub ec_codewords: usize,
+    // modules of the block that were sent to the solver to match the target
+    pub requested_bits: usize,
+    // requested modules that the solver could still set, the rest were already fixed by earlier ones
+    pub image_bits: usize,
+    // requested modules that match the target in the finished code
+    pub matched_bits: usize,
+    // requested modules that were refused because they could make a number too big, and had to be cleared
+    pub refused_bits: usize,
+}
+
+impl BlockReport {
+    // the number of codewords that can be misread before the block can no longer be decoded
+    pub fn ec_margin(&self) -> usize {
+        self.ec_codewords / 2
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub blocks: Vec<BlockReport>,
+    // data modules that are part of a run that looks like the 1:1:3:1:1 ratio of a finder pattern
+    pub finder_like: usize,
+    // data modules in a neighbourhood that is almost entirely dark or light
+    pub imbalanced: usize,
+    // fraction of all modules that are dark
+    pub dark_ratio: f32,
+    // 0 to 100, higher is easier to scan
+    pub score: f32,
+    // the code with finder-like runs marked in red and imbalanced areas marked in blue
+    pub heatmap: RgbaImage,
+}
+
+// side length of the window that the dark/light balance is measured in, the size of a finder pattern
+const BALANCE_WINDOW: usize = 7;
+
+impl Report {
+    // modules is true for dark modules
+    pub(crate) fn new(
+        blocks: Vec<BlockReport>,
+        modules: &[Vec<bool>],
+        patterns: &[Vec<Option<Pattern>>],
+        module_size: u32,
+    ) -> Self {
+        let side_len = modules.len();
+
+        let finder_like = finder_like_runs(modules, patterns);
+        let imbalance = imbalance(modules);
+
+        let dark = modules.iter().flatten().filter(|dark| **dark).count();
+        let dark_ratio = dark as f32 / (side_len * side_len) as f32;
+
+        // risk of each data module, function patterns are drawn by the spec and dont count towards the score
+        let mut risk_sum = 0.0;
+        let mut data_modules = 0;
+        let (mut finder_count, mut imbalanced_count) = (0, 0);
+        for y in 0..side_len {
+            for x in 0..side_len {
+                if patterns[y][x].is_some() {
+                    continue;
+                }
+                data_modules += 1;
+                finder_count += finder_like[y][x] as usize;
+                imbalanced_count += (imbalance[y][x] > 0.5) as usize;
+                risk_sum += if finder_like[y][x] {
+                    1.0
+                } else {
+                    imbalance[y][x]
+                };
+            }
+        }
+
+        let mean_risk = risk_sum / data_modules.max(1) as f32;
+        let score = 100.0 * (1.0 - mean_risk) * (1.0 - (dark_ratio - 0.5).abs());
+
+        let mut heatmap =
+            RgbaImage::new(side_len as u32 * module_size, side_len as u32 * module_size);
+        for y in 0..side_len {
+            for x in 0..side_len {
+                // the code is drawn faded so the marks stand out
+                let base = if modules[y][x] { 96.0 } else { 224.0 };
+                let color = if patterns[y][x].is_none() && finder_like[y][x] {
+                    Rgba([255, 0, 0, 255])
+                } else if patterns[y][x].is_none() {
+                    let t = imbalance[y][x];
+                    let mix = |to: f32| (base * (1.0 - t) + to * t) as u8;
+                    Rgba([mix(0.0), mix(64.0), mix(255.0), 255])
+                } else {
+                    Rgba([base as u8, base as u8, base as u8, 255])
+                };
+                for py in 0..module_size {
+                    for px in 0..module_size {
+                        heatmap.put_pixel(
+                            x as u32 * module_size + px,
+                            y as u32 * module_size + py,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+
+        Report {
+            blocks,
+            finder_like: finder_count,
+            imbalanced: imbalanced_count,
+            dark_ratio,
+            score,
+            heatmap,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "block  data  ec  ec margin  requested  image bits  refused  matched"
+        )?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>5}  {:>4}  {:>2}  {:>9}  {:>9}  {:>10}  {:>7}  {:>7}",
+                i,
+                block.data_codewords,
+                block.ec_codewords,
+                block.ec_margin(),
+                block.requested_bits,
+                block.image_bits,
+                block.refused_bits,
+                block.matched_bits,
+            )?;
+        }
+        writeln!(f, "finder-like modules: {}", self.finder_like)?;
+        writeln!(f, "imbalanced modules: {}", self.imbalanced)?;
+        writeln!(f, "dark modules: {:.1}%", self.dark_ratio * 100.0)?;
+        write!(f, "score: {:.1} / 100", self.score)
+    }
+}
+
+// marks every module in a dark-light-dark-light-dark run along a row or column with roughly the 1:1:3:1:1 ratio of
+// a finder pattern, which can make readers look for the code in the wrong place.
+// runs through the real finder patterns are skipped
+fn finder_like_runs(modules: &[Vec<bool>], patterns: &[Vec<Option<Pattern>>]) -> Vec<Vec<bool>> {
+    let side_len = modules.len();
+    let mut marked = vec![vec![false; side_len]; side_len];
+
+    for horizontal in [true, false] {
+        for line in 0..side_len {
+            let at = |i: usize| if horizontal { (i, line) } else { (line, i) };
+
+            // (start, length, dark) of each run of same colored modules
+            let mut runs: Vec<(usize, usize, bool)> = Vec::new();
+            for i in 0..side_len {
+                let (x, y) = at(i);
+                match runs.last_mut() {
+                    Some(run) if run.2 == modules[y][x] => run.1 += 1,
+                    _ => runs.push((i, 1, modules[y][x])),
+                }
+            }
+
+            for window in runs.windows(5) {
+                if !window[0].2 {
+                    continue;
+                }
+                let unit = window.iter().map(|run| run.1).sum::<usize>() as f32 / 7.0;
+                let expected = [1.0, 1.0, 3.0, 1.0, 1.0];
+                let matches = window
+                    .iter()
+                    .zip(expected)
+                    .all(|(run, ratio)| (run.1 as f32 - unit * ratio).abs() <= unit * ratio / 2.0);
+
+                let (x, y) = at(window[2].0);
+                if !matches || patterns[y][x] == Some(Pattern::Finder) {
+                    continue;
+                }
+
+                let (start, end) = (window[0].0, window[4].0 + window[4].1);
+                for i in start..end {
+                    let (x, y) = at(i);
+                    marked[y][x] = true;
+                }
+            }
+        }
+    }
+
+    marked
+}
+
+// how far the balance of dark and light modules around each module is from even, 0 until one color makes up 80% of
+// the window, rising to 1 when the window is a single color
+fn imbalance(modules: &[Vec<bool>]) -> Vec<Vec<f32>> {
+    let side_len = modules.len() as i32;
+    let reach = BALANCE_WINDOW as i32 / 2;
+
+    (0..side_len)
+        .map(|y| {
+            (0..side_len)
+                .map(|x| {
+                    let (mut dark, mut total) = (0, 0);
+                    for wy in (y - reach).max(0)..(y + reach + 1).min(side_len) {
+                        for wx in (x - reach).max(0)..(x + reach + 1).min(side_len) {
+                            dark += modules[wy as usize][wx as usize] as u32;
+                            total += 1;
+                        }
+                    }
+                    let skew = (dark as f32 / total as f32 - 0.5).abs() * 2.0;
+                    ((skew - 0.6) / 0.4).clamp(0.0, 1.0)
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,274 @@
+// a small http server that builds codes on demand. POST /build takes a json object with the target image as base64
+// and the settings, and returns the code as a png or an svg
+
+use anyhow::{anyhow, Context};
+use base64::Engine;
+use image::ImageFormat;
+use qart::qr;
+use qart::svg::svg;
+use qart::target::{Target, Threshold};
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// requests with a bigger body than this are refused
+const MAX_BODY: u64 = 32 * 1024 * 1024;
+// codes with a bigger side length in pixels than this are refused, so a request cant make the server run out of memory
+const MAX_SIDE: u64 = 4096;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BuildRequest {
+    // the target image file, base64 encoded
+    image: String,
+    // the url the code points to
+    payload: String,
+    version: u8,
+    // a number between 0 and 255, or "auto". the default is 128
+    threshold: Option<ThresholdArg>,
+    #[serde(default)]
+    format: Format,
+    seed: Option<u64>,
+    // the side length of each module in pixels, only used by pngs. the default is 5
+    module_size: Option<u32>,
+    // the side length of the png in pixels, instead of module_size
+    width: Option<u32>,
+    // the width of the light margin around the code in modules. the default is 10
+    quiet_zone: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ThresholdArg {
+    Fixed(u8),
+    Named(String),
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Png,
+    Svg,
+}
+
+// targets that were already prepared, by the hash of the image, the version and the module size they were prepared
+// for. the module size is the one chosen from the width and quiet zone when the width is set. a target also depends on
+// the levels, priority, mask, transparency, placement and styles, but the server always leaves those at their
+// defaults, and the threshold and seed are only used once the target is made. the oldest target is dropped once there
+// are more than capacity
+type CacheKey = (u64, u8, u32);
+
+struct Cache {
+    targets: HashMap<CacheKey, Arc<Target>>,
+    order: VecDeque<CacheKey>,
+    capacity: usize,
+}
+
+impl Cache {
+    fn get(&self, key: &CacheKey) -> Option<Arc<Target>> {
+        self.targets.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: CacheKey, target: Arc<Target>) {
+        if self.capacity == 0 || self.targets.insert(key, target).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.targets.remove(&oldest);
+            }
+        }
+    }
+}
+
+// limits how many codes are built at once, each build already uses a thread for every block of the code
+struct Jobs {
+    running: Mutex<usize>,
+    finished: Condvar,
+    max: usize,
+}
+
+// frees up the job when it is dropped, so a build that panics doesnt take it forever
+struct Job<'a>(&'a Jobs);
+
+impl Jobs {
+    // waits until fewer than max jobs are running
+    fn start(&self) -> Job<'_> {
+        let mut running = self
+            .finished
+            .wait_while(self.running.lock().unwrap(), |running| *running >= self.max)
+            .unwrap();
+        *running += 1;
+        Job(self)
+    }
+}
+
+impl Drop for Job<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+struct State {
+    cache: Mutex<Cache>,
+    jobs: Jobs,
+}
+
+// runs until the process is stopped. requests are handled by a fixed number of worker threads, and the rest wait for
+// one of them without their bodies being read
+pub fn serve(
+    address: &str,
+    jobs: usize,
+    connections: usize,
+    cache_size: usize,
+) -> anyhow::Result<()> {
+    let server =
+        Arc::new(Server::http(address).map_err(|e| anyhow!("Could not listen on {address}: {e}"))?);
+    println!("listening on http://{address}");
+
+    let state = Arc::new(State {
+        cache: Mutex::new(Cache {
+            targets: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: cache_size,
+        }),
+        jobs: Jobs {
+            running: Mutex::new(0),
+            finished: Condvar::new(),
+            max: jobs.max(1),
+        },
+    });
+
+    let workers = (0..connections.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    // a build that panics only loses its own request, not the worker
+                    let handled = panic::catch_unwind(AssertUnwindSafe(|| handle(request, &state)));
+                    if handled.is_err() {
+                        log::error!("A request panicked");
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| anyhow!("A worker thread panicked"))?;
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, state: &State) {
+    log::info!("{} {}", request.method(), request.url());
+    let response = match (request.method(), request.url()) {
+        (Method::Post, "/build") => match build(&mut request, state) {
+            Ok((body, content_type)) => Response::from_data(body).with_header(header(content_type)),
+            Err(e) => {
+                log::info!("could not build code: {:#}", e);
+                Response::from_string(format!("{:#}", e)).with_status_code(400)
+            }
+        },
+        (_, "/build") => Response::from_string("Codes are built with POST").with_status_code(405),
+        _ => Response::from_string("Not found").with_status_code(404),
+    };
+    if let Err(e) = request.respond(response) {
+        log::error!("Could not send response: {}", e);
+    }
+}
+
+fn header(content_type: &str) -> Header {
+    Header::from_bytes("Content-Type", content_type).expect("content types are valid headers")
+}
+
+// returns the code and its content type
+fn build(request: &mut Request, state: &State) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)
+        .context("Could not read request")?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(anyhow!("Request is bigger than {MAX_BODY} bytes"));
+    }
+    let settings: BuildRequest = serde_json::from_slice(&body).context("Invalid request")?;
+    let image = base64::engine::general_purpose::STANDARD
+        .decode(&settings.image)
+        .context("image is not valid base64")?;
+
+    let defaults = qr::Options::default();
+    let options = qr::Options {
+        module_size: settings.module_size.unwrap_or(defaults.module_size),
+        threshold: match settings.threshold {
+            None => defaults.threshold,
+            Some(ThresholdArg::Fixed(threshold)) => Threshold::Fixed(threshold),
+            Some(ThresholdArg::Named(threshold)) => threshold.parse()?,
+        },
+        width: settings.width,
+        quiet_zone: settings.quiet_zone.unwrap_or(defaults.quiet_zone),
+        seed: settings.seed,
+        ..defaults
+    };
+    let side = options.image_side(settings.version)?;
+    if side > MAX_SIDE {
+        return Err(anyhow!(
+            "the code would be {side} pixels wide, the most is {MAX_SIDE}"
+        ));
+    }
+
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    let key = (
+        hasher.finish(),
+        settings.version,
+        options.module_size_of(settings.version)?,
+    );
+
+    let output = {
+        let _job = state.jobs.start();
+        let cached = state.cache.lock().unwrap().get(&key);
+        let target = match cached {
+            Some(target) => target,
+            None => {
+                let decoded =
+                    image::load_from_memory(&image).context("Could not read target image")?;
+                let target = Arc::new(qr::prepare_target(settings.version, decoded, &options)?);
+                state.cache.lock().unwrap().insert(key, Arc::clone(&target));
+                target
+            }
+        };
+        qr::build_prepared(settings.version, settings.payload, &target, &options)
+            .context("Could not create QR Code")?
+    };
+
+    match settings.format {
+        Format::Png => {
+            let mut png = Vec::new();
+            output
+                .image
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .context("Could not encode png")?;
+            Ok((png, "image/png"))
+        }
+        Format::Svg => {
+            let modules = output.modules.unwrap_or_default();
+            Ok((
+                svg(&modules, options.quiet_zone).into_bytes(),
+                "image/svg+xml",
+            ))
+        }
+    }
+}
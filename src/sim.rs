@@ -0,0 +1,262 @@
+// degrades a finished code the way printing and a phone camera would, and checks whether it still decodes
+
+use crate::decode::{self, Homography};
+use anyhow::{anyhow, Context};
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, DynamicImage, GrayImage, Luma};
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+// the strongest degradations a trial can apply, each trial picks a random strength between half of these and all of them
+#[derive(Clone, Copy, Debug)]
+pub struct Profile {
+    // standard deviation of the gaussian blur, in modules
+    pub blur: f32,
+    // the lowest jpeg quality, 1-100
+    pub jpeg_quality: u8,
+    // standard deviation of the noise added to each pixel, in brightness levels
+    pub noise: f32,
+    // how far each corner can be moved for the perspective warp, as a fraction of the image size
+    pub perspective: f32,
+    // the fewest pixels per module the image is scaled down to
+    pub resolution: f32,
+}
+
+impl Profile {
+    pub const MILD: Profile = Profile {
+        blur: 0.2,
+        jpeg_quality: 75,
+        noise: 10.0,
+        perspective: 0.03,
+        resolution: 4.0,
+    };
+    pub const PHONE: Profile = Profile {
+        blur: 0.35,
+        jpeg_quality: 50,
+        noise: 20.0,
+        perspective: 0.08,
+        resolution: 3.0,
+    };
+    pub const HARSH: Profile = Profile {
+        blur: 0.5,
+        jpeg_quality: 25,
+        noise: 35.0,
+        perspective: 0.15,
+        resolution: 2.0,
+    };
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::PHONE
+    }
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mild" => Ok(Profile::MILD),
+            "phone" => Ok(Profile::PHONE),
+            "harsh" => Ok(Profile::HARSH),
+            _ => Err(anyhow!("profile must be mild, phone or harsh")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Degradation {
+    Blur,
+    Jpeg,
+    Noise,
+    Perspective,
+    Downscale,
+    // all of the above, in the order a camera would apply them
+    Combined,
+}
+
+impl Degradation {
+    pub const ALL: [Degradation; 6] = [
+        Degradation::Blur,
+        Degradation::Jpeg,
+        Degradation::Noise,
+        Degradation::Perspective,
+        Degradation::Downscale,
+        Degradation::Combined,
+    ];
+}
+
+impl fmt::Display for Degradation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Degradation::Blur => "blur",
+            Degradation::Jpeg => "jpeg",
+            Degradation::Noise => "noise",
+            Degradation::Perspective => "perspective",
+            Degradation::Downscale => "downscale",
+            Degradation::Combined => "combined",
+        };
+        f.pad(name)
+    }
+}
+
+pub struct Verification {
+    // the text decoded from the undegraded image, that every variant is compared against
+    pub text: String,
+    pub version: u8,
+    // (degradation, successful decodes, trials)
+    pub results: Vec<(Degradation, usize, usize)>,
+}
+
+impl Verification {
+    pub fn success_rate(&self) -> f32 {
+        let successes: usize = self.results.iter().map(|r| r.1).sum();
+        let trials: usize = self.results.iter().map(|r| r.2).sum();
+        successes as f32 / trials.max(1) as f32
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the numbers that fill the rest of the code arent interesting, so long texts are cut off
+        let text = match self.text.char_indices().nth(60) {
+            Some((end, _)) => format!("{}...", &self.text[..end]),
+            None => self.text.clone(),
+        };
+        writeln!(f, "version {}: {}", self.version, text)?;
+        for (degradation, successes, trials) in self.results.iter() {
+            writeln!(f, "{:<12} {}/{}", degradation, successes, trials)?;
+        }
+        write!(f, "success rate: {:.0}%", self.success_rate() * 100.0)
+    }
+}
+
+// decodes the image as is, then decodes `trials` degraded variants for each kind of degradation
+pub fn verify(
+    image: &DynamicImage,
+    profile: Profile,
+    trials: usize,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Verification> {
+    let image = image.to_luma8();
+    let clean = decode::decode(&image).context("Could not decode the undegraded image")?;
+
+    let mut results = Vec::with_capacity(Degradation::ALL.len());
+    for degradation in Degradation::ALL {
+        let mut successes = 0;
+        for _ in 0..trials {
+            let degraded = degrade(&image, degradation, profile, clean.module_size, rng)?;
+            match decode::decode(&degraded) {
+                Ok(decoded) if decoded.text == clean.text => successes += 1,
+                Ok(_) => log::debug!("{degradation} decoded to the wrong text"),
+                Err(e) => log::debug!("{degradation} failed: {e:#}"),
+            }
+        }
+        results.push((degradation, successes, trials));
+    }
+
+    Ok(Verification {
+        text: clean.text,
+        version: clean.version,
+        results,
+    })
+}
+
+// applies one degradation at a random strength, module_size is the side length of a module in pixels
+pub fn degrade(
+    image: &GrayImage,
+    degradation: Degradation,
+    profile: Profile,
+    module_size: f64,
+    rng: &mut impl Rng,
+) -> anyhow::Result<GrayImage> {
+    let strength = rng.gen_range(0.5..=1.0);
+
+    Ok(match degradation {
+        Degradation::Blur => imageops::blur(image, profile.blur * strength * module_size as f32),
+        Degradation::Jpeg => {
+            let quality = 100 - ((100 - profile.jpeg_quality as u32) as f32 * strength) as u8;
+            jpeg(image, quality.max(1))?
+        }
+        Degradation::Noise => noise(image, profile.noise * strength, rng),
+        Degradation::Perspective => perspective(image, profile.perspective * strength, rng),
+        Degradation::Downscale => {
+            let scale = profile.resolution as f64 / strength as f64 / module_size;
+            let width = ((image.width() as f64 * scale).round() as u32).max(1);
+            let height = ((image.height() as f64 * scale).round() as u32).max(1);
+            imageops::resize(image, width, height, imageops::FilterType::Triangle)
+        }
+        Degradation::Combined => {
+            let warped = degrade(image, Degradation::Perspective, profile, module_size, rng)?;
+            let blurred = degrade(&warped, Degradation::Blur, profile, module_size, rng)?;
+            let small = degrade(&blurred, Degradation::Downscale, profile, module_size, rng)?;
+            let module_size = module_size * small.width() as f64 / blurred.width() as f64;
+            let noisy = degrade(&small, Degradation::Noise, profile, module_size, rng)?;
+            degrade(&noisy, Degradation::Jpeg, profile, module_size, rng)?
+        }
+    })
+}
+
+fn jpeg(image: &GrayImage, quality: u8) -> anyhow::Result<GrayImage> {
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, quality)
+        .encode_image(image)
+        .context("Could not compress image")?;
+    Ok(image::load_from_memory(&bytes)
+        .context("Could not read compressed image")?
+        .to_luma8())
+}
+
+fn noise(image: &GrayImage, deviation: f32, rng: &mut impl Rng) -> GrayImage {
+    let mut noisy = image.clone();
+    for pixel in noisy.pixels_mut() {
+        // https://en.wikipedia.org/wiki/Box%E2%80%93Muller_transform
+        let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let u2: f32 = rng.gen();
+        let normal = (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+        pixel.0[0] = (pixel.0[0] as f32 + normal * deviation).clamp(0.0, 255.0) as u8;
+    }
+    noisy
+}
+
+// moves each corner of the image inwards by up to amount of its size, and fills the uncovered area with white.
+// corners only move inwards so the code stays in frame, like it would when someone is aiming a camera at it
+fn perspective(image: &GrayImage, amount: f32, rng: &mut impl Rng) -> GrayImage {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let reach = amount as f64 * width.max(height);
+    let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)];
+    let moved = corners.map(|(x, y)| {
+        let inwards = |v: f64, side: f64| if v == 0.0 { 1.0 } else { -1.0 } * side.min(reach);
+        (
+            x + inwards(x, width) * rng.gen_range(0.0..=1.0),
+            y + inwards(y, height) * rng.gen_range(0.0..=1.0),
+        )
+    });
+
+    // maps pixels of the warped image back to the original, so every pixel gets a value
+    let Some(transform) = Homography::new(&moved, &corners) else {
+        return image.clone();
+    };
+
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let (sx, sy) = transform.map(x as f64 + 0.5, y as f64 + 0.5);
+        Luma([bilinear(image, sx - 0.5, sy - 0.5)])
+    })
+}
+
+fn bilinear(image: &GrayImage, x: f64, y: f64) -> u8 {
+    let get = |x: f64, y: f64| {
+        if x < 0.0 || y < 0.0 || x >= image.width() as f64 || y >= image.height() as f64 {
+            255.0
+        } else {
+            image.get_pixel(x as u32, y as u32).0[0] as f64
+        }
+    };
+    let (fx, fy) = (x.floor(), y.floor());
+    let (tx, ty) = (x - fx, y - fy);
+    let top = get(fx, fy) * (1.0 - tx) + get(fx + 1.0, fy) * tx;
+    let bottom = get(fx, fy + 1.0) * (1.0 - tx) + get(fx + 1.0, fy + 1.0) * tx;
+    (top * (1.0 - ty) + bottom * ty).round() as u8
+}
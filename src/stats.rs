@@ -0,0 +1,105 @@
+// numbers describing how closely a built code follows its target, and where the time went, for comparing settings
+
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+// std::time::Instant panics in the browser, this is the same type everywhere else
+use web_time::Instant;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Stats {
+    // fraction of the modules asked to match the target that do, 0-1
+    pub match_rate: f32,
+    // the same, but each module counts as much as its priority, so matching detailed areas counts for more
+    pub weighted_match_rate: f32,
+    // modules the solver could set to follow the target
+    pub controllable_modules: usize,
+    // modules holding the encoded url and the headers around it, which can never follow the target
+    pub fixed_modules: usize,
+    // modules that were refused to keep the numbers that fill the code valid
+    pub numeric_repairs: usize,
+    pub phases: Vec<Phase>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    pub millis: f64,
+}
+
+impl Stats {
+    pub fn total_time(&self) -> Duration {
+        Duration::from_secs_f64(self.phases.iter().map(|phase| phase.millis).sum::<f64>() / 1000.0)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "matching modules: {:.1}%", self.match_rate * 100.0)?;
+        writeln!(
+            f,
+            "weighted matching modules: {:.1}%",
+            self.weighted_match_rate * 100.0
+        )?;
+        writeln!(f, "controllable modules: {}", self.controllable_modules)?;
+        writeln!(f, "fixed modules: {}", self.fixed_modules)?;
+        writeln!(f, "numeric repairs: {}", self.numeric_repairs)?;
+        for phase in self.phases.iter() {
+            writeln!(f, "{:<12} {:.2}ms", phase.name, phase.millis)?;
+        }
+        write!(f, "total time: {:?}", self.total_time())
+    }
+}
+
+// records how long each phase takes, each phase ends when the next one starts
+pub(crate) struct Timer {
+    phases: Vec<Phase>,
+    current: &'static str,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn start(name: &'static str) -> Self {
+        Timer {
+            phases: Vec::new(),
+            current: name,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn next(&mut self, name: &'static str) {
+        self.phases.push(Phase {
+            name: self.current,
+            millis: self.start.elapsed().as_secs_f64() * 1000.0,
+        });
+        self.current = name;
+        self.start = Instant::now();
+    }
+
+    pub fn finish(mut self) -> Vec<Phase> {
+        self.next("");
+        self.phases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_records_every_phase_in_order() {
+        let mut timer = Timer::start("first");
+        timer.next("second");
+        timer.next("third");
+        let phases = timer.finish();
+        let names = phases.iter().map(|phase| phase.name).collect::<Vec<_>>();
+        assert_eq!(names, ["first", "second", "third"]);
+
+        let stats = Stats {
+            phases,
+            ..Default::default()
+        };
+        let sum = stats.phases.iter().map(|phase| phase.millis).sum::<f64>();
+        assert!((stats.total_time().as_secs_f64() * 1000.0 - sum).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,31 @@
+// how the modules of the code are drawn
+
+// how the finder, alignment and timing patterns are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PatternStyle {
+    // the whole module is black or white
+    #[default]
+    Solid,
+    // only a dot in the center of each module is black or white, and the rest of the module follows the target.
+    // the dot is a third of the module, but at least min_core pixels wide. finder patterns are only drawn this way
+    // if finders is set, since readers rely on them the most
+    Dots {
+        min_core: u32,
+        finders: bool,
+    },
+}
+
+// how the data and error correction modules are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ModuleStyle {
+    // the whole module is black or white
+    #[default]
+    Solid,
+    // only a square core in the center of each module is black or white, and the rest of the module shows the target
+    // in gray, kept on the same side of the threshold as the module. core is the width of the core as a fraction of
+    // the module for modules that dont match the target, and modules that do match get a core half as wide, since the
+    // target around them already looks like them
+    Cores {
+        core: f32,
+    },
+}
@@ -0,0 +1,25 @@
+// draws a finished code as an svg, from whether each module is dark. only the modules are drawn, so the image that
+// shows through dotted patterns is left out
+
+use std::fmt::Write;
+
+// border is the width of the light margin around the code, in modules
+pub fn svg(modules: &[Vec<bool>], border: u32) -> String {
+    let size = modules.len() as u32 + 2 * border;
+
+    // every dark module is a 1x1 square in a single path, which keeps the file small
+    let mut path = String::new();
+    for (y, row) in modules.iter().enumerate() {
+        for (x, dark) in row.iter().enumerate() {
+            if *dark {
+                let _ = write!(path, "M{},{}h1v1h-1z", x as u32 + border, y as u32 + border);
+            }
+        }
+    }
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {size} {size}\" shape-rendering=\"crispEdges\">\
+         <rect width=\"{size}\" height=\"{size}\" fill=\"#fff\"/>\
+         <path d=\"{path}\" fill=\"#000\"/></svg>\n"
+    )
+}
@@ -1,66 +1,588 @@
-// a function to handle opening up the target image and extracting brightness/contrast data from it
+// a function to handle scaling the target image and extracting brightness/priority data from it
 
 // formula to get percieved brightness: ((299 * r + 587 * g + 114 * b) + 500) / 1000
 // brightness > 125 -> white, else black
 
-use anyhow::Context;
-use image::{self, DynamicImage, GenericImageView, Rgb};
+use anyhow::anyhow;
+use image::imageops::{self, FilterType};
+use image::{self, DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
 use crate::consts::Version;
+use crate::layout;
+use crate::qr::Options;
+use crate::style::{ModuleStyle, PatternStyle};
+use std::str::FromStr;
 
-pub fn get_target_scale(path: String, side_len: usize) -> anyhow::Result<Vec<Vec<(u32, u8)>>> {
-    let target = image::open(path).context("Could not open target image")?;
+// how the brightness threshold between black and white modules is chosen
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    // pixels darker than this value are black
+    Fixed(u8),
+    // computed from the brightness histogram of the target with otsu's method
+    Otsu,
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold::Fixed(128)
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" | "otsu" => Ok(Threshold::Otsu),
+            n => n
+                .parse()
+                .map(Threshold::Fixed)
+                .map_err(|_| anyhow!("threshold must be \"auto\" or a number between 0 and 255")),
+        }
+    }
+}
+
+impl Threshold {
+    pub fn resolve(self, target: &[Vec<Pixel>]) -> u8 {
+        match self {
+            Threshold::Fixed(t) => t,
+            // dont care pixels are left for the rest of the code, so they shouldnt move the threshold
+            Threshold::Otsu => otsu(
+                target
+                    .iter()
+                    .flatten()
+                    .filter(|pixel| !pixel.dont_care())
+                    .map(|pixel| pixel.brightness),
+            ),
+        }
+    }
+}
+
+// adjustments made to the brightness of the target before the priority and threshold are computed
+#[derive(Clone, Copy, Debug)]
+pub struct Levels {
+    // stretch the brightness histogram so that it covers the full 0-255 range
+    pub stretch: bool,
+    // values below 1 brighten the target, values above 1 darken it
+    pub gamma: f32,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels {
+            stretch: false,
+            gamma: 1.0,
+        }
+    }
+}
+
+// a grayscale image the same shape as the target, where white areas must match the target, black areas dont matter,
+// and gray areas scale the priority of their modules
+#[derive(Clone, Debug)]
+pub struct Mask(pub GrayImage);
+
+// what happens to the transparent parts of the target
+#[derive(Clone, Copy, Debug)]
+pub enum Transparency {
+    // transparent pixels are blended onto this rgb color
+    Background([u8; 3]),
+    // transparent modules dont matter, like the black areas of a mask
+    DontCare,
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Transparency::Background([255, 255, 255])
+    }
+}
+
+impl FromStr for Transparency {
+    type Err = anyhow::Error;
+
+    // either "dont-care", or a background color as a hex code like "#ffffff"
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "dont-care" {
+            return Ok(Transparency::DontCare);
+        }
+
+        let hex = s.trim_start_matches('#');
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Transparency::Background([r, g, b])),
+            _ => Err(anyhow!(
+                "transparency must be \"dont-care\" or a hex color like \"#ffffff\""
+            )),
+        }
+    }
+}
+
+// the target image at a single module
+#[derive(Clone, Copy, Debug)]
+pub struct Pixel {
+    pub priority: u32,
+    pub brightness: u8,
+    // how much the module matters according to the mask, 255 = must match, 0 = dont care
+    pub care: u8,
+}
+
+impl Pixel {
+    pub fn must_match(&self) -> bool {
+        self.care == 255
+    }
+
+    pub fn dont_care(&self) -> bool {
+        self.care == 0
+    }
+
+    // the priority scaled by how much the module matters
+    pub fn weight(&self) -> u32 {
+        (self.priority as u64 * self.care as u64 / 255) as u32
+    }
+
+    // modules are matched to the target in descending order of this key
+    pub fn order(&self) -> (bool, u32) {
+        (self.must_match(), self.weight())
+    }
+}
 
-    let scaled = target.resize_exact(
-        side_len as u32,
-        side_len as u32,
-        image::imageops::FilterType::Gaussian,
-    );
+// how the target is fitted into the square code
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Fit {
+    // squash the target to the shape of the code
+    #[default]
+    Stretch,
+    // scale the target to fit inside the code, the leftover space is treated like transparent pixels
+    Contain,
+    // scale the target to cover the code, cropping the sides around a focal point given in fractions of its size
+    Cover {
+        focus_x: f32,
+        focus_y: f32,
+    },
+}
+
+// a rectangle of the target in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// where the target is drawn on the code
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    // only use this part of the target
+    pub crop: Option<Crop>,
+    pub fit: Fit,
+    // the side length of the target as a fraction of the side length of the code
+    pub scale: f32,
+    // how far the target is moved from the center of the code, in modules
+    pub offset_x: i32,
+    pub offset_y: i32,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            crop: None,
+            fit: Fit::default(),
+            scale: 1.0,
+            offset_x: 0,
+            offset_y: 0,
+        }
+    }
+}
+
+// how the placement of the target is decided
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Layout {
+    // the placement is used as given
+    #[default]
+    Manual,
+    // the scale and offset of the placement are chosen so the most important parts of the target
+    // land on data and ec modules instead of function patterns
+    AvoidPatterns,
+}
 
-    let brightness = make_brightness_array(scaled);
+pub struct Target {
+    pub pixels: Vec<Vec<Pixel>>,
+    // the brightness of the target at the resolution of the drawn code, without the border.
+    // only made when the code is drawn with a style that shows the target inside of modules
+    pub detail: Option<GrayImage>,
+}
+
+// a target from width * height * 4 bytes of rgba, row by row, as the bindings take it. from_raw alone also accepts extra
+// bytes at the end, which would mean the width or height is wrong, so the length has to match exactly
+#[cfg(any(feature = "ffi", feature = "python", feature = "wasm-bindgen"))]
+pub(crate) fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> Option<DynamicImage> {
+    (rgba.len() as u64 == width as u64 * height as u64 * 4)
+        .then(|| RgbaImage::from_raw(width, height, rgba))
+        .flatten()
+        .map(DynamicImage::ImageRgba8)
+}
+
+// a crop has to be inside of the target, otherwise there is nothing left of it to place
+pub(crate) fn check_crop(target: &DynamicImage, placement: &Placement) -> anyhow::Result<()> {
+    let Some(crop) = placement.crop else {
+        return Ok(());
+    };
+    if crop.width == 0 || crop.height == 0 {
+        return Err(anyhow!("the crop must be at least 1 pixel wide and high"));
+    }
+    let right = crop.x as u64 + crop.width as u64;
+    let bottom = crop.y as u64 + crop.height as u64;
+    if right > target.width() as u64 || bottom > target.height() as u64 {
+        return Err(anyhow!(
+            "the crop {},{},{},{} doesnt fit inside of the {}x{} image",
+            crop.x,
+            crop.y,
+            crop.width,
+            crop.height,
+            target.width(),
+            target.height()
+        ));
+    }
+    Ok(())
+}
+
+// module_size is the side length of each module in pixels that the detail is made at
+pub fn get_target_scale(
+    target: DynamicImage,
+    version: Version,
+    module_size: u32,
+    options: &Options,
+) -> Target {
+    let side_len = crate::consts::side_len_of_version(version) as usize;
+
+    // the color of transparent pixels is usually black, so they are blended onto the background before scaling
+    // to keep them from bleeding into the rest of the image
+    let background = match options.transparency {
+        Transparency::Background(color) => color,
+        Transparency::DontCare => [255, 255, 255],
+    };
+    let opacity = alpha(&target);
+    let target = composite(target, background).into_rgba8();
+
+    let placement = match options.layout {
+        Layout::Manual => options.placement,
+        Layout::AvoidPatterns => {
+            let full = Placement {
+                scale: 1.0,
+                offset_x: 0,
+                offset_y: 0,
+                ..options.placement
+            };
+            let base = scale(&target, &opacity, side_len, options, &full);
+            let placement = avoid_patterns(&base, version, full);
+            log::info!(
+                "placed target at scale {} and offset ({}, {})",
+                placement.scale,
+                placement.offset_x,
+                placement.offset_y
+            );
+            placement
+        }
+    };
+
+    let shows_target =
+        options.patterns != PatternStyle::Solid || options.modules != ModuleStyle::Solid;
+    let detail = shows_target.then(|| {
+        let [r, g, b] = background;
+        let fill = Rgba([r, g, b, 255]);
+        let scaled = place(&target, side_len, module_size, &placement, fill);
+
+        let mut brightness = make_brightness_array(DynamicImage::ImageRgba8(scaled));
+        adjust_levels(&mut brightness, options.levels);
+
+        GrayImage::from_fn(brightness.len() as u32, brightness.len() as u32, |x, y| {
+            image::Luma([brightness[y as usize][x as usize]])
+        })
+    });
+
+    Target {
+        pixels: scale(&target, &opacity, side_len, options, &placement),
+        detail,
+    }
+}
+
+// pulls the modules of a frame of an animation towards the colors they had in the frame before, which is what makes
+// them flicker otherwise. stability times the highest priority in the frame is the weight of keeping the old color:
+// modules where the target agrees with it are matched sooner, and modules where the target matters less than that
+// ask for the old color instead
+pub fn stabilize(target: &mut Target, previous: &[Vec<bool>], threshold: u8, stability: f32) {
+    let highest = target
+        .pixels
+        .iter()
+        .flatten()
+        .map(|pixel| pixel.priority)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let keep = (highest as f32 * stability) as u32;
+
+    for (row, previous) in target.pixels.iter_mut().zip(previous) {
+        for (pixel, dark) in row.iter_mut().zip(previous) {
+            if (pixel.brightness < threshold) == *dark {
+                pixel.priority = pixel.priority.saturating_add(keep);
+            } else if pixel.priority < keep {
+                pixel.brightness = if *dark { 0 } else { 255 };
+                pixel.priority = keep - pixel.priority;
+            }
+        }
+    }
+}
+
+fn scale(
+    target: &RgbaImage,
+    opacity: &GrayImage,
+    side_len: usize,
+    options: &Options,
+    placement: &Placement,
+) -> Vec<Vec<Pixel>> {
+    let background = match options.transparency {
+        Transparency::Background(color) => color,
+        Transparency::DontCare => [255, 255, 255],
+    };
+
+    // anything that ends up outside of the target is treated like a transparent pixel
+    let [r, g, b] = background;
+    let scaled = place(target, side_len, 1, placement, Rgba([r, g, b, 255]));
+    let opacity = place(opacity, side_len, 1, placement, Luma([0]));
+
+    let mut brightness = make_brightness_array(DynamicImage::ImageRgba8(scaled));
+    adjust_levels(&mut brightness, options.levels);
+
+    let priorities = priority(target, side_len, options, placement);
+
+    let care = options
+        .mask
+        .as_ref()
+        .map(|mask| place(&mask.0, side_len, 1, placement, Luma([255])));
+    let opacity = match options.transparency {
+        Transparency::DontCare => Some(opacity),
+        Transparency::Background(_) => None,
+    };
 
     let mut result = Vec::with_capacity(side_len);
 
     for y in 0..side_len {
         let mut row = Vec::with_capacity(side_len);
         for x in 0..side_len {
-            row.push((
-                get_contrast(x as u32, y as u32, &brightness),
-                brightness[y][x],
-            ))
+            row.push(Pixel {
+                priority: priorities[y][x],
+                brightness: brightness[y][x],
+                care: [&care, &opacity]
+                    .iter()
+                    .filter_map(|care| care.as_ref())
+                    .fold(255, |acc, care| {
+                        (acc as u32 * care.get_pixel(x as u32, y as u32).0[0] as u32 / 255) as u8
+                    }),
+            })
         }
         result.push(row)
     }
 
-    Ok(result)
+    result
 }
 
-pub fn preview(path: String, version: Version, brightness_threshold: u8) -> anyhow::Result<()> {
-    let target = image::open(path).context("Could not open target image")?;
+// the priority of every module. it is worked out on the target by itself, at about the size it is drawn at, and then
+// placed like the target, so priority maps and regions line up with the parts of the target they describe. modules
+// that the target doesnt cover have no priority
+fn priority(
+    target: &RgbaImage,
+    side_len: usize,
+    options: &Options,
+    placement: &Placement,
+) -> Vec<Vec<u32>> {
+    let (width, height) = (target.width().max(1), target.height().max(1));
+    let (scale_x, scale_y) = modules_per_pixel(width, height, side_len, placement);
+    let small_width = ((width as f32 * scale_x).round() as u32).max(1);
+    let small_height = ((height as f32 * scale_y).round() as u32).max(1);
+    let small = imageops::resize(target, small_width, small_height, FilterType::Gaussian);
 
-    let side_len = crate::consts::side_len_of_version(version);
+    let mut brightness = make_brightness_array(DynamicImage::ImageRgba8(small));
+    adjust_levels(&mut brightness, options.levels);
+    let priorities = options.priority.priority(&brightness);
+    let map = ImageBuffer::from_fn(small_width, small_height, |x, y| {
+        Luma([priorities[y as usize][x as usize] as f32])
+    });
 
-    let scaled = target.resize_exact(side_len, side_len, image::imageops::FilterType::Gaussian);
+    // the crop is in pixels of the full size target
+    let resize = |v: u32, from: u32, to: u32| (v as u64 * to as u64 / from as u64) as u32;
+    let placement = Placement {
+        crop: placement.crop.map(|crop| Crop {
+            x: resize(crop.x, width, small_width),
+            y: resize(crop.y, height, small_height),
+            width: resize(crop.width, width, small_width).max(1),
+            height: resize(crop.height, height, small_height).max(1),
+        }),
+        ..*placement
+    };
+    let placed = place(&map, side_len, 1, &placement, Luma([0.0]));
 
-    let brightness = make_brightness_array(scaled);
+    (0..side_len as u32)
+        .map(|y| {
+            (0..side_len as u32)
+                .map(|x| placed.get_pixel(x, y).0[0].round() as u32)
+                .collect()
+        })
+        .collect()
+}
 
-    let mut result = image::ImageBuffer::new(side_len, side_len);
+// how many modules wide and high each pixel of the target is drawn, the same way as place
+fn modules_per_pixel(
+    width: u32,
+    height: u32,
+    side_len: usize,
+    placement: &Placement,
+) -> (f32, f32) {
+    let (width, height) = match placement.crop {
+        Some(crop) => (
+            crop.width.min(width.saturating_sub(crop.x)).max(1),
+            crop.height.min(height.saturating_sub(crop.y)).max(1),
+        ),
+        None => (width, height),
+    };
+    let art = ((side_len as f32 * placement.scale).round() as u32).max(1) as f32;
+    let (width, height) = (width as f32, height as f32);
 
-    for y in 0..side_len {
-        for x in 0..side_len {
-            result.put_pixel(
-                x,
-                y,
-                if brightness[y as usize][x as usize] < brightness_threshold {
-                    Rgb([0, 0, 0])
-                } else {
-                    Rgb([255 as u8, 255, 255])
-                },
-            )
+    match placement.fit {
+        Fit::Stretch => (art / width, art / height),
+        Fit::Contain => {
+            let scale = (art / width).min(art / height);
+            (scale, scale)
+        }
+        Fit::Cover { .. } => {
+            let scale = art / width.min(height);
+            (scale, scale)
         }
     }
+}
 
-    result.save("preview.png").context("Could not save preview image")?;
-    Ok(())
+// tries out smaller scales and offsets of the target, and picks the one that keeps the largest share of the target's
+// priority on data and ec modules. shrinking the target is penalized a little so it only happens when it helps.
+// the target at full scale is sampled instead of rescaling it for every try
+fn avoid_patterns(base: &[Vec<Pixel>], version: Version, placement: Placement) -> Placement {
+    let patterns = layout::function_patterns(version);
+    let side_len = base.len() as i32;
+
+    // larger codes take bigger steps so the search stays quick
+    let step = (side_len / 40).max(1);
+    let reach = (side_len / 10).max(2);
+
+    let mut best = (f64::MIN, placement);
+
+    for scale in [1.0, 0.95, 0.9, 0.85, 0.8, 0.75, 0.7] {
+        let art = ((side_len as f32 * scale).round() as i32).max(1);
+
+        // offsets closer to the center are tried first so they win ties
+        let mut offsets = (-reach / step..=reach / step)
+            .map(|o| o * step)
+            .collect::<Vec<_>>();
+        offsets.sort_by_key(|o| o.abs());
+
+        for &offset_y in offsets.iter() {
+            for &offset_x in offsets.iter() {
+                let left = (side_len - art) / 2 + offset_x;
+                let top = (side_len - art) / 2 + offset_y;
+
+                // the parts of the target that are moved off of the code count as lost
+                let mut total: u64 = 0;
+                let mut kept: u64 = 0;
+                for y in top..(top + art) {
+                    for x in left..(left + art) {
+                        let base_x = (x - left) * side_len / art;
+                        let base_y = (y - top) * side_len / art;
+                        let weight = base[base_y as usize][base_x as usize].weight() as u64;
+
+                        total += weight;
+                        if (0..side_len).contains(&x)
+                            && (0..side_len).contains(&y)
+                            && patterns[y as usize][x as usize].is_none()
+                        {
+                            kept += weight;
+                        }
+                    }
+                }
+
+                let score = kept as f64 / total.max(1) as f64 - 0.25 * (1.0 - scale as f64);
+
+                if score > best.0 {
+                    best = (
+                        score,
+                        Placement {
+                            scale,
+                            offset_x,
+                            offset_y,
+                            ..placement
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    best.1
+}
+
+// crops, fits and moves the image onto a canvas of side_len x side_len modules filled with the fill color,
+// where each module is resolution pixels wide
+fn place<P>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    side_len: usize,
+    resolution: u32,
+    placement: &Placement,
+    fill: P,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: image::Pixel + 'static,
+{
+    let filter = FilterType::Gaussian;
+
+    let image = match placement.crop {
+        Some(crop) => imageops::crop_imm(image, crop.x, crop.y, crop.width, crop.height).to_image(),
+        None => image.clone(),
+    };
+
+    // the target is always a whole number of modules wide, so it lines up at every resolution
+    let art_modules = ((side_len as f32 * placement.scale).round() as u32).max(1);
+    let art = art_modules * resolution;
+    let (width, height) = (image.width().max(1) as f32, image.height().max(1) as f32);
+
+    let (fitted, dx, dy) = match placement.fit {
+        Fit::Stretch => (imageops::resize(&image, art, art, filter), 0, 0),
+        Fit::Contain => {
+            let scale = (art as f32 / width).min(art as f32 / height);
+            let fit_width = ((width * scale).round() as u32).clamp(1, art);
+            let fit_height = ((height * scale).round() as u32).clamp(1, art);
+            (
+                imageops::resize(&image, fit_width, fit_height, filter),
+                (art - fit_width) / 2,
+                (art - fit_height) / 2,
+            )
+        }
+        Fit::Cover { focus_x, focus_y } => {
+            let side = image.width().min(image.height());
+            let left =
+                (focus_x * width - side as f32 / 2.0).clamp(0.0, (image.width() - side) as f32);
+            let top =
+                (focus_y * height - side as f32 / 2.0).clamp(0.0, (image.height() - side) as f32);
+            let square = imageops::crop_imm(&image, left as u32, top as u32, side, side);
+            (imageops::resize(&*square, art, art, filter), 0, 0)
+        }
+    };
+
+    let corner = (side_len as i64 - art_modules as i64) / 2;
+    let left = (corner + placement.offset_x as i64) * resolution as i64 + dx as i64;
+    let top = (corner + placement.offset_y as i64) * resolution as i64 + dy as i64;
+
+    let canvas_len = side_len as u32 * resolution;
+    let mut canvas = ImageBuffer::from_pixel(canvas_len, canvas_len, fill);
+    imageops::replace(&mut canvas, &fitted, left, top);
+    canvas
 }
 
 fn make_brightness_array(image: DynamicImage) -> Vec<Vec<u8>> {
@@ -79,34 +601,304 @@ fn make_brightness_array(image: DynamicImage) -> Vec<Vec<u8>> {
     brightness_array
 }
 
-fn get_contrast(target_x: u32, target_y: u32, brightness: &Vec<Vec<u8>>) -> u32 {
-    let range = 5;
+fn composite(image: DynamicImage, background: [u8; 3]) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
 
-    let mut n = 0;
-    let mut sum: usize = 0;
-    let mut sum_sequence: usize = 0;
+    let mut rgba = image.into_rgba8();
+    rgba.pixels_mut().for_each(|p| {
+        let alpha = p[3] as u32;
+        for c in 0..3 {
+            p[c] = ((p[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+        p[3] = 255;
+    });
+    DynamicImage::ImageRgba8(rgba)
+}
 
-    for offset_y in 0..(range * 2) {
-        for offset_x in 0..(range * 2) {
-            let pixel_y = (target_y as i32 - range) + offset_y;
-            let pixel_x = (target_x as i32 - range) + offset_x;
-            if pixel_y >= 0
-                && (pixel_y as usize) < brightness.len()
-                && pixel_x >= 0
-                && (pixel_x as usize) < brightness[0].len()
-            {
-                let v = brightness[pixel_y as usize][pixel_x as usize];
+fn alpha(image: &DynamicImage) -> GrayImage {
+    if !image.color().has_alpha() {
+        return GrayImage::from_pixel(image.width(), image.height(), image::Luma([255]));
+    }
 
-                sum += v as usize;
-                sum_sequence += v as usize * v as usize;
+    let rgba = image.to_rgba8();
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        image::Luma([rgba.get_pixel(x, y)[3]])
+    })
+}
 
-                n += 1;
+fn histogram(brightness: impl Iterator<Item = u8>) -> [usize; 256] {
+    let mut histogram = [0; 256];
+    brightness.for_each(|v| histogram[v as usize] += 1);
+    histogram
+}
+
+fn adjust_levels(brightness: &mut [Vec<u8>], levels: Levels) {
+    let mut lookup: [u8; 256] = std::array::from_fn(|v| v as u8);
+
+    if levels.stretch {
+        let histogram = histogram(brightness.iter().flatten().copied());
+        let total: usize = histogram.iter().sum();
+
+        // ignore the darkest and brightest 1% of pixels so that a few outliers dont stop the stretch
+        let clip = total / 100;
+        let mut low = 0;
+        let mut seen = 0;
+        while low < 255 && seen + histogram[low] <= clip {
+            seen += histogram[low];
+            low += 1;
+        }
+        let mut high = 255;
+        seen = 0;
+        while high > low && seen + histogram[high] <= clip {
+            seen += histogram[high];
+            high -= 1;
+        }
+
+        if high > low {
+            for (v, out) in lookup.iter_mut().enumerate() {
+                let stretched = (v.clamp(low, high) - low) * 255 / (high - low);
+                *out = stretched as u8;
             }
         }
     }
 
-    let avg = sum / n;
-    let contrast = sum_sequence / n - avg * avg;
+    if levels.gamma != 1.0 {
+        for out in lookup.iter_mut() {
+            *out = (255.0 * (*out as f32 / 255.0).powf(levels.gamma)).round() as u8;
+        }
+    }
+
+    brightness
+        .iter_mut()
+        .flatten()
+        .for_each(|v| *v = lookup[*v as usize]);
+}
+
+// https://en.wikipedia.org/wiki/Otsu%27s_method
+// returns the threshold that maximizes the variance between the pixels below it and the pixels at or above it
+pub(crate) fn otsu(brightness: impl Iterator<Item = u8>) -> u8 {
+    let histogram = histogram(brightness);
+
+    let total: usize = histogram.iter().sum();
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(v, n)| (v * n) as f64)
+        .sum();
+
+    let mut best = (0.0, 128);
+    let mut below = 0;
+    let mut below_sum = 0.0;
+
+    for threshold in 1..256 {
+        below += histogram[threshold - 1];
+        below_sum += ((threshold - 1) * histogram[threshold - 1]) as f64;
+
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+
+        let below_mean = below_sum / below as f64;
+        let above_mean = (total_sum - below_sum) / above as f64;
+        let variance =
+            below as f64 * above as f64 * (below_mean - above_mean) * (below_mean - above_mean);
+
+        if variance > best.0 {
+            best = (variance, threshold as u8);
+        }
+    }
+
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a version 1 target, which is 21 modules wide
+    fn prepare(target: DynamicImage, options: &Options) -> Vec<Vec<Pixel>> {
+        get_target_scale(target, Version::new(1).unwrap(), 1, options).pixels
+    }
+
+    #[test]
+    fn masks_set_how_much_modules_matter() {
+        let target = DynamicImage::ImageLuma8(GrayImage::from_pixel(42, 42, Luma([0])));
+        // white on the left, gray in the middle and black on the right
+        let mask = GrayImage::from_fn(42, 42, |x, _| Luma([[255, 128, 0][x as usize / 14]]));
+        let options = Options {
+            mask: Some(Mask(mask)),
+            ..Default::default()
+        };
+        let pixels = prepare(target, &options);
+        assert!(pixels[10][1].must_match());
+        assert!(!pixels[10][10].must_match() && !pixels[10][10].dont_care());
+        assert!(pixels[10][19].dont_care());
+        assert_eq!(pixels[10][19].weight(), 0);
+    }
 
-    contrast as u32
+    #[test]
+    fn transparent_pixels_are_blended_onto_the_background() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| {
+            Rgba([0, 0, 0, [0, 128][x as usize]])
+        }));
+        let blended = composite(image, [255, 0, 255]).into_rgba8();
+        assert_eq!(blended.get_pixel(0, 0).0, [255, 0, 255, 255]);
+        assert_eq!(blended.get_pixel(1, 0).0, [127, 0, 127, 255]);
+    }
+
+    #[test]
+    fn transparent_modules_can_be_left_out() {
+        // a black square on a transparent background
+        let target = DynamicImage::ImageRgba8(RgbaImage::from_fn(42, 42, |x, y| {
+            let inside = (10..32).contains(&x) && (10..32).contains(&y);
+            Rgba([0, 0, 0, if inside { 255 } else { 0 }])
+        }));
+
+        let background = prepare(target.clone(), &Options::default());
+        assert!(background[0][0].brightness > 250 && !background[0][0].dont_care());
+        assert!(background[10][10].brightness < 5);
+
+        let options = Options {
+            transparency: Transparency::DontCare,
+            ..Default::default()
+        };
+        let dont_care = prepare(target, &options);
+        assert!(dont_care[0][0].dont_care());
+        assert!(dont_care[10][10].must_match());
+    }
+
+    #[test]
+    fn transparency_parses() {
+        assert!(matches!(
+            "#ff8000".parse(),
+            Ok(Transparency::Background([255, 128, 0]))
+        ));
+        assert!(matches!("dont-care".parse(), Ok(Transparency::DontCare)));
+        assert!("#ff80".parse::<Transparency>().is_err());
+    }
+
+    // a version 5 target, which is 37 modules wide, where only the modules in the square matter
+    fn important_square(left: usize, top: usize, side: usize) -> Vec<Vec<Pixel>> {
+        (0..37)
+            .map(|y| {
+                (0..37)
+                    .map(|x| {
+                        let inside =
+                            (left..left + side).contains(&x) && (top..top + side).contains(&y);
+                        Pixel {
+                            priority: if inside { 100 } else { 0 },
+                            brightness: 0,
+                            care: 255,
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn avoid_patterns_leaves_clear_targets_alone() {
+        let version = Version::new(5).unwrap();
+        let placement =
+            avoid_patterns(&important_square(12, 12, 13), version, Placement::default());
+        assert_eq!(placement.scale, 1.0);
+        assert_eq!((placement.offset_x, placement.offset_y), (0, 0));
+    }
+
+    #[test]
+    fn avoid_patterns_moves_targets_off_of_finders() {
+        let version = Version::new(5).unwrap();
+        let placement = avoid_patterns(&important_square(0, 0, 8), version, Placement::default());
+        assert!(
+            placement.scale < 1.0 || placement.offset_x > 0 || placement.offset_y > 0,
+            "{placement:?}"
+        );
+    }
+
+    #[test]
+    fn stabilize_favors_the_previous_frame() {
+        let pixel = |priority, brightness| Pixel {
+            priority,
+            brightness,
+            care: 255,
+        };
+        // the highest priority is 100, so keeping the old color is worth 50
+        let mut target = Target {
+            pixels: vec![vec![
+                pixel(100, 0),
+                pixel(10, 0),
+                pixel(80, 255),
+                pixel(20, 255),
+            ]],
+            detail: None,
+        };
+        stabilize(&mut target, &[vec![true, true, true, true]], 128, 0.5);
+        let pixels = &target.pixels[0];
+
+        // modules that agree with the previous frame are matched sooner
+        assert_eq!(pixels[0].priority, 150);
+        assert_eq!(pixels[1].priority, 60);
+        // important modules that dont are left alone
+        assert_eq!((pixels[2].priority, pixels[2].brightness), (80, 255));
+        // and the rest ask for the old color instead
+        assert_eq!((pixels[3].priority, pixels[3].brightness), (30, 0));
+    }
+
+    #[test]
+    fn otsu_splits_two_levels() {
+        let brightness = std::iter::repeat_n(40, 300).chain(std::iter::repeat_n(200, 100));
+        let threshold = otsu(brightness);
+        assert!(40 < threshold && threshold <= 200, "{threshold}");
+    }
+
+    #[test]
+    fn otsu_ignores_dont_care_pixels() {
+        let pixel = |brightness, care| Pixel {
+            priority: 1,
+            brightness,
+            care,
+        };
+        // the dont care pixels are all brighter than the rest, so they would pull the threshold up
+        let mut row = vec![pixel(20, 255); 50];
+        row.extend(vec![pixel(100, 255); 50]);
+        row.extend(vec![pixel(250, 0); 200]);
+        let threshold = Threshold::Otsu.resolve(&[row]);
+        assert!(20 < threshold && threshold <= 100, "{threshold}");
+    }
+
+    #[test]
+    fn thresholds_parse() {
+        assert!(matches!("auto".parse(), Ok(Threshold::Otsu)));
+        assert!(matches!("90".parse(), Ok(Threshold::Fixed(90))));
+        assert!("300".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn stretch_covers_the_full_range() {
+        let mut brightness = vec![(100..=150).collect::<Vec<u8>>()];
+        let levels = Levels {
+            stretch: true,
+            gamma: 1.0,
+        };
+        adjust_levels(&mut brightness, levels);
+        assert_eq!(brightness[0].first(), Some(&0));
+        assert_eq!(brightness[0].last(), Some(&255));
+        assert!(brightness[0].windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn gamma_above_1_darkens() {
+        let mut brightness = vec![vec![0, 64, 128, 192, 255]];
+        let levels = Levels {
+            stretch: false,
+            gamma: 2.0,
+        };
+        adjust_levels(&mut brightness, levels);
+        assert_eq!(brightness[0][0], 0);
+        assert_eq!(brightness[0][4], 255);
+        assert!(brightness[0][2] < 128);
+    }
 }
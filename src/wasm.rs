@@ -0,0 +1,106 @@
+// javascript bindings for making codes in the browser. images go in and out as rgba bytes, row by row, the same layout
+// as the data of an ImageData
+
+pub use crate::bindings::Options;
+use crate::qr;
+use crate::target;
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Options {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[wasm_bindgen]
+pub struct Code {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    threshold: u8,
+    stats: Option<String>,
+}
+
+#[wasm_bindgen]
+impl Code {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // the rgba bytes of the code, can be passed to the ImageData constructor as a Uint8ClampedArray
+    #[wasm_bindgen(getter)]
+    pub fn pixels(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+
+    // the brightness threshold that was used, useful when it was chosen automatically
+    #[wasm_bindgen(getter)]
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    // how closely the code follows the image and how long each phase took, as json. undefined for previews
+    #[wasm_bindgen(getter)]
+    pub fn stats(&self) -> Option<String> {
+        self.stats.clone()
+    }
+}
+
+impl From<qr::Output> for Code {
+    fn from(output: qr::Output) -> Self {
+        Code {
+            width: output.image.width(),
+            height: output.image.height(),
+            threshold: output.threshold,
+            stats: output
+                .stats
+                .and_then(|stats| serde_json::to_string(&stats).ok()),
+            pixels: output.image.into_raw(),
+        }
+    }
+}
+
+fn target(rgba: Vec<u8>, width: u32, height: u32) -> Result<DynamicImage, JsError> {
+    target::from_rgba(rgba, width, height)
+        .ok_or_else(|| JsError::new("Image data does not match its width and height"))
+}
+
+// builds a functional qr code that looks like the image
+#[wasm_bindgen]
+pub fn build(
+    version: u8,
+    url: String,
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    options: &Options,
+) -> Result<Code, JsError> {
+    qr::build(version, url, target(rgba, width, height)?, &options.into())
+        .map(Code::from)
+        .map_err(|e| JsError::new(&format!("Could not create QR Code: {:#}", e)))
+}
+
+// quickly shows which modules will follow the image at one pixel per module, with the modules left to the rest of the
+// code in gray
+#[wasm_bindgen]
+pub fn preview(
+    version: u8,
+    url: String,
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    options: &Options,
+) -> Result<Code, JsError> {
+    qr::preview(version, url, target(rgba, width, height)?, &options.into())
+        .map(Code::from)
+        .map_err(|e| JsError::new(&format!("{:#}", e)))
+}